@@ -0,0 +1,60 @@
+use std::{collections::BTreeMap, time::Instant};
+
+use atomsimulation::{
+    presets::walls, random::Rng, update_particles, Element, Particle, SimulationConfig,
+};
+use cgmath::Vector2;
+
+/// Places hydrogen atoms on a jittered grid, `spacing` is the distance between grid points
+fn particles(count: usize, spacing: f32, rng: &mut Rng) -> Vec<Particle> {
+    let columns = (count as f32).sqrt().ceil() as usize;
+    let offset = columns as f32 * spacing * 0.5;
+    (0..count)
+        .map(|i| {
+            Particle::new(
+                Vector2 {
+                    x: (i % columns) as f32 * spacing - offset + rng.range(0.0, 0.1),
+                    y: (i / columns) as f32 * spacing - offset + rng.range(0.0, 0.1),
+                },
+                Vector2 {
+                    x: rng.range(-5.0, 5.0),
+                    y: rng.range(-5.0, 5.0),
+                },
+                Element::Hydrogen,
+            )
+        })
+        .collect()
+}
+
+fn bench(name: &str, count: usize, spacing: f32, steps: usize) {
+    let mut rng = Rng::new(count as u64);
+    let mut particles = particles(count, spacing, &mut rng);
+    let half_size = (count as f32).sqrt().ceil() * spacing * 0.5 + 1.0;
+    let mut rectangles = walls(half_size * 2.0, half_size * 2.0);
+    let mut bonds = BTreeMap::new();
+    let config = SimulationConfig::default();
+
+    let start = Instant::now();
+    for _ in 0..steps {
+        update_particles(
+            &mut particles,
+            &mut bonds,
+            &mut rectangles,
+            &config,
+            1.0 / 60.0,
+        );
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{name:>6} {count:>6} particles: {:>10.2} steps/sec ({steps} steps in {elapsed:.3}s)",
+        steps as f64 / elapsed,
+    );
+}
+
+fn main() {
+    for (count, steps) in [(100, 1000), (1000, 50), (10000, 2)] {
+        // Particles are about 1.1 units across so these spacings give lots of contacts vs almost none
+        bench("dense", count, 1.2, steps);
+        bench("sparse", count, 5.0, steps);
+    }
+}
@@ -13,6 +13,7 @@ struct Camera {
     position: vec2<f32>,
     aspect: f32,
     zoom: f32,
+    edge_softness: f32,
 };
 
 @group(0)
@@ -22,13 +23,33 @@ var<uniform> camera: Camera;
 struct Circle {
     position: vec2<f32>,
     color: vec3<f32>,
-    radius: f32,
+    semi_axes: vec2<f32>,
+    orientation: f32,
+    sprite_index: u32,
+    outline: u32,
+    alpha: f32,
 };
 
 @group(1)
 @binding(0)
 var<storage, read> circles: array<Circle>;
 
+@group(1)
+@binding(1)
+var sprite_texture: texture_2d<f32>;
+
+@group(1)
+@binding(2)
+var sprite_sampler: sampler;
+
+const SPRITE_COUNT: f32 = 2.0;
+const NO_SPRITE: u32 = 0xffffffffu;
+
+const HOVER_OUTLINE: u32 = 1u;
+const SELECTED_OUTLINE: u32 = 2u;
+// The outline covers the outer part of the circle, measured in squared uv distance from the center
+const OUTLINE_START: f32 = 0.7;
+
 @vertex
 fn vertex(input: VertexInput) -> VertexOutput {
     var output: VertexOutput;
@@ -39,8 +60,15 @@ fn vertex(input: VertexInput) -> VertexOutput {
         f32((input.vertex_index >> 1u) & 1u) * 2.0 - 1.0,
     );
 
-    let world_position = output.uv * circles[input.circle_index].radius + circles[input.circle_index].position;
+    let circle = circles[input.circle_index];
+    let scaled = output.uv * circle.semi_axes;
+    let rotation = vec2<f32>(cos(circle.orientation), sin(circle.orientation));
+    let world_position = vec2<f32>(
+        scaled.x * rotation.x - scaled.y * rotation.y,
+        scaled.x * rotation.y + scaled.y * rotation.x,
+    ) + circle.position;
 
+    // Dividing x by the aspect keeps the circles round at any window size
     output.clip_position = vec4<f32>((world_position - camera.position) * camera.zoom / vec2<f32>(camera.aspect, 1.0), 0.0, 1.0);
 
     return output;
@@ -48,9 +76,40 @@ fn vertex(input: VertexInput) -> VertexOutput {
 
 @fragment
 fn pixel(input: VertexOutput) -> @location(0) vec4<f32> {
-    if dot(input.uv, input.uv) > 1.0 {
+    let distance = length(input.uv);
+    // Measured before discarding because derivatives need every pixel of the quad
+    let pixel_size = fwidth(distance);
+    if distance > 1.0 {
         discard;
     }
 
-    return vec4<f32>(circles[input.circle_index].color, 1.0);
+    var alpha = 1.0;
+    if camera.edge_softness > 0.0 {
+        alpha = clamp((1.0 - distance) / (pixel_size * camera.edge_softness), 0.0, 1.0);
+    }
+    return vec4<f32>(circle_color(input), alpha * circles[input.circle_index].alpha);
+}
+
+fn circle_color(input: VertexOutput) -> vec3<f32> {
+    let circle = circles[input.circle_index];
+    if dot(input.uv, input.uv) > OUTLINE_START {
+        if circle.outline == SELECTED_OUTLINE {
+            return vec3<f32>(1.0, 0.8, 0.2);
+        }
+        if circle.outline == HOVER_OUTLINE {
+            return mix(circle.color, vec3<f32>(1.0), 0.5);
+        }
+    }
+
+    if circle.sprite_index == NO_SPRITE {
+        return circle.color;
+    }
+
+    // The sprites are laid out in a horizontal strip
+    let sprite_uv = vec2<f32>(
+        (f32(circle.sprite_index) + input.uv.x * 0.5 + 0.5) / SPRITE_COUNT,
+        input.uv.y * 0.5 + 0.5,
+    );
+    let sprite = textureSampleLevel(sprite_texture, sprite_sampler, sprite_uv, 0.0);
+    return circle.color * sprite.rgb;
 }
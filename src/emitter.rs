@@ -0,0 +1,136 @@
+use cgmath::{prelude::*, Vector2};
+
+use crate::{
+    physics::{Element, Particle, Scene},
+    random::Rng,
+};
+
+/// Spawns particles at a steady rate, for continuous flows
+#[derive(Clone)]
+pub struct Emitter {
+    pub position: Vector2<f32>,
+    pub velocity: Vector2<f32>,
+    /// Largest angle in radians the velocity of a spawned particle is turned by either way
+    pub spread: f32,
+    /// Particles spawned per second
+    pub rate: f32,
+    pub element: Element,
+    /// The fraction of a particle left over from earlier ticks
+    pending: f32,
+    rng: Rng,
+}
+
+impl Emitter {
+    /// Emitters with the same seed turn their particles by the same angles
+    pub fn new(
+        position: Vector2<f32>,
+        velocity: Vector2<f32>,
+        element: Element,
+        seed: u64,
+    ) -> Emitter {
+        Emitter {
+            position,
+            velocity,
+            spread: 0.0,
+            rate: 10.0,
+            element,
+            pending: 0.0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Spawns the particles that became due over `dt` seconds, without letting the scene grow
+    /// past `max_particles`, and returns how many were spawned
+    pub fn tick(&mut self, scene: &mut Scene, dt: f32, max_particles: usize) -> usize {
+        self.pending += self.rate * dt;
+        let due = self.pending.floor();
+        self.pending -= due;
+        let count = (due as usize).min(max_particles.saturating_sub(scene.particles.len()));
+
+        for k in 0..count {
+            let angle = self.rng.range(-self.spread, self.spread);
+            let (sin, cos) = angle.sin_cos();
+            let velocity = Vector2 {
+                x: self.velocity.x * cos - self.velocity.y * sin,
+                y: self.velocity.x * sin + self.velocity.y * cos,
+            };
+            // Move each particle as far as it would have gone since it was due, so the ones
+            // spawned in the same tick don't start on top of each other unless the emitter
+            // has no velocity
+            let age = (self.pending + (count - 1 - k) as f32) / self.rate;
+            scene.particles.push(Particle::new(
+                self.position + velocity * age,
+                velocity,
+                self.element,
+            ));
+        }
+        count
+    }
+
+    /// Where the emitter marker's arrow points, scaled to the given length
+    pub fn direction(&self, length: f32) -> Vector2<f32> {
+        if self.velocity.is_zero() {
+            Vector2::zero()
+        } else {
+            self.velocity.normalize_to(length)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn emits_at_its_rate() {
+        let mut scene = Scene {
+            particles: vec![],
+            bonds: BTreeMap::new(),
+            rectangles: vec![],
+        };
+        let mut emitter = Emitter::new(
+            Vector2::zero(),
+            Vector2::new(1.0, 0.0),
+            Element::Hydrogen,
+            0,
+        );
+        emitter.rate = 10.0;
+        for _ in 0..60 {
+            emitter.tick(&mut scene, 1.0 / 60.0, usize::MAX);
+        }
+        assert!((9..=11).contains(&scene.particles.len()));
+
+        for _ in 0..600 {
+            emitter.tick(&mut scene, 1.0 / 60.0, 50);
+        }
+        assert_eq!(scene.particles.len(), 50);
+    }
+
+    #[test]
+    fn emitters_with_different_seeds_spread_differently() {
+        let velocities = |seed: u64| {
+            let mut scene = Scene {
+                particles: vec![],
+                bonds: BTreeMap::new(),
+                rectangles: vec![],
+            };
+            let mut emitter = Emitter::new(
+                Vector2::zero(),
+                Vector2::new(1.0, 0.0),
+                Element::Hydrogen,
+                seed,
+            );
+            emitter.spread = 1.0;
+            emitter.tick(&mut scene, 1.0, usize::MAX);
+            scene
+                .particles
+                .iter()
+                .map(|particle| particle.velocity)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(velocities(1), velocities(1));
+        assert_ne!(velocities(1), velocities(2));
+    }
+}
@@ -0,0 +1,10 @@
+pub mod emitter;
+pub mod physics;
+pub mod presets;
+pub mod random;
+pub mod scene_file;
+pub mod scene_toml;
+pub mod settings;
+pub mod svg;
+
+pub use physics::{update_particles, Bond, Element, Particle, Rectangle, Scene, SimulationConfig};
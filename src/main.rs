@@ -1,5 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 
+use atomsimulation::{
+    emitter::Emitter,
+    physics::{
+        apply_impulse, bond_count, center_of_mass, electrons_to_share, region_stats, Bond,
+        CollisionEvent, Contact, ContactSolver, CustomElement, DecayRule, Element, Ellipse,
+        EquilibriumDetector, ForceFlags, ForceModel, Particle, ProductDirection, ReactionRule,
+        Scene, SimulationConfig, SolverModel, SpatialHash, SpeedHistogram, StageOrder, StepReport,
+        WallMaterial,
+    },
+    presets,
+    random::Rng,
+    scene_file, scene_toml,
+    settings::Settings,
+    svg,
+};
 use cgmath::{prelude::*, Vector2, Vector3};
 use eframe::{
     egui,
@@ -7,222 +22,3842 @@ use eframe::{
     wgpu::{self},
     NativeOptions, Renderer,
 };
-use physics::{update_particles, Bond, Element, Particle, Rectangle};
-use rendering::{create_render_state, GpuCamera, GpuCircle, GpuRectangle, RenderCallback};
+use rendering::{
+    create_render_state, render_to_png, GpuCamera, GpuCircle, GpuRectangle, RenderCallback,
+};
+
+mod rendering;
+
+struct Camera {
+    position: Vector2<f32>,
+    /// The view is always `2 / zoom` world units tall, so resizing the window only reveals
+    /// more or less of the world horizontally and never stretches it
+    zoom: f32,
+    /// How fast the camera keeps panning after a drag, in world units per second
+    velocity: Vector2<f32>,
+    /// How fast the camera keeps zooming after a zoom input, as the log of the zoom per second
+    zoom_velocity: f32,
+}
+
+impl Camera {
+    const MIN_ZOOM: f32 = 0.001;
+    const MAX_ZOOM: f32 = 100.0;
+    /// How much a single scroll notch or key press zooms by
+    const ZOOM_STEP: f32 = 0.9;
+
+    fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+    }
+
+    /// The world direction that points to the bottom of the screen. The camera can't rotate
+    /// yet so this is always world down, but gravity already follows it
+    fn screen_down(&self) -> Vector2<f32> {
+        Vector2 { x: 0.0, y: -1.0 }
+    }
+
+    /// Zooms right away and, with inertia, keeps zooming a little afterwards
+    fn zoom_input(&mut self, factor: f32, inertia: bool) {
+        const ZOOM_FLICK: f32 = 4.0;
+        self.zoom_by(factor);
+        if inertia {
+            self.zoom_velocity += factor.ln() * ZOOM_FLICK;
+        }
+    }
+
+    fn apply_inertia(&mut self, dt: f32) {
+        const DECAY_RATE: f32 = 5.0;
+        // Below these the motion is too slow to notice, so stop instead of drifting forever
+        const MIN_SCREEN_SPEED: f32 = 0.01;
+        const MIN_ZOOM_SPEED: f32 = 0.01;
+
+        self.position += self.velocity * dt;
+        self.zoom_by((self.zoom_velocity * dt).exp());
+        let decay = (-DECAY_RATE * dt).exp();
+        self.velocity *= decay;
+        self.zoom_velocity *= decay;
+        if self.velocity.magnitude() * self.zoom < MIN_SCREEN_SPEED {
+            self.velocity = Vector2::zero();
+        }
+        if self.zoom_velocity.abs() < MIN_ZOOM_SPEED {
+            self.zoom_velocity = 0.0;
+        }
+    }
+
+    /// Centers the view on an object `size` units across and zooms so it takes up about a
+    /// quarter of the view height
+    fn focus(&mut self, position: Vector2<f32>, size: f32) {
+        self.position = position;
+        self.zoom = (0.5 / size.max(f32::EPSILON)).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        self.velocity = Vector2::zero();
+        self.zoom_velocity = 0.0;
+    }
+
+    fn world_to_screen(&self, rect: egui::Rect, world_position: Vector2<f32>) -> egui::Pos2 {
+        let aspect = rect.width() / rect.height();
+        let relative_position = (world_position - self.position) * self.zoom;
+        rect.center()
+            + egui::vec2(
+                relative_position.x / aspect * rect.width() * 0.5,
+                -relative_position.y * rect.height() * 0.5,
+            )
+    }
+
+    fn screen_to_world(&self, rect: egui::Rect, screen_position: egui::Pos2) -> Vector2<f32> {
+        let aspect = rect.width() / rect.height();
+        let mouse_position = ((screen_position - rect.left_top()) / rect.size() * 2.0
+            - egui::vec2(1.0, 1.0))
+            * egui::vec2(1.0, -1.0);
+        Vector2 {
+            x: mouse_position.x * aspect / self.zoom + self.position.x,
+            y: mouse_position.y / self.zoom + self.position.y,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SelectedObject {
+    Particle(usize),
+    Rectangle(usize),
+}
+
+impl SelectedObject {
+    /// Particles are picked before the rectangles they're on
+    fn at(
+        scene: &Scene,
+        custom_elements: &[CustomElement],
+        world_position: Vector2<f32>,
+    ) -> Option<SelectedObject> {
+        scene
+            .particle_at(world_position, custom_elements)
+            .map(SelectedObject::Particle)
+            .or_else(|| {
+                scene
+                    .rectangle_at(world_position)
+                    .map(SelectedObject::Rectangle)
+            })
+    }
+
+    /// Where this object ends up in the scene after `removed` is removed
+    fn after_removal(self, removed: SelectedObject) -> Option<SelectedObject> {
+        match (self, removed) {
+            _ if self == removed => None,
+            (SelectedObject::Particle(i), SelectedObject::Particle(removed)) if i > removed => {
+                Some(SelectedObject::Particle(i - 1))
+            }
+            (SelectedObject::Rectangle(i), SelectedObject::Rectangle(removed)) if i > removed => {
+                Some(SelectedObject::Rectangle(i - 1))
+            }
+            _ => Some(self),
+        }
+    }
+
+    /// The center of the object and how far across it is
+    fn extent(&self, scene: &Scene, custom_elements: &[CustomElement]) -> (Vector2<f32>, f32) {
+        match *self {
+            SelectedObject::Particle(i) => {
+                let particle = &scene.particles[i];
+                (particle.position, particle.radius(custom_elements) * 2.0)
+            }
+            SelectedObject::Rectangle(i) => {
+                let rectangle = &scene.rectangles[i];
+                (rectangle.position, rectangle.size.x.max(rectangle.size.y))
+            }
+        }
+    }
+
+    fn exists_in(&self, scene: &Scene) -> bool {
+        match *self {
+            SelectedObject::Particle(i) => i < scene.particles.len(),
+            SelectedObject::Rectangle(i) => i < scene.rectangles.len(),
+        }
+    }
+
+    /// The same object once `old` has been replaced by `new`, particles are found by
+    /// `Particle::id` since their index can differ between the two
+    fn find_in(self, old: &Scene, new: &Scene) -> Option<SelectedObject> {
+        match self {
+            SelectedObject::Particle(i) => {
+                let id = old.particles.get(i)?.id;
+                new.particles
+                    .iter()
+                    .position(|particle| particle.id == id)
+                    .map(SelectedObject::Particle)
+            }
+            SelectedObject::Rectangle(_) => Some(self).filter(|object| object.exists_in(new)),
+        }
+    }
+}
+
+/// What happens to particles with less kinetic energy than the threshold, so the hot ones
+/// stand out
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EnergyFilter {
+    Off,
+    Dim,
+    Hide,
+}
+
+impl EnergyFilter {
+    const ALL: [EnergyFilter; 3] = [EnergyFilter::Off, EnergyFilter::Dim, EnergyFilter::Hide];
+    /// Opacity of the dimmed particles
+    const DIM_ALPHA: f32 = 0.2;
+
+    fn name(&self) -> &'static str {
+        match self {
+            EnergyFilter::Off => "Off",
+            EnergyFilter::Dim => "Dim",
+            EnergyFilter::Hide => "Hide",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    const ALL: [Corner; 4] = [
+        Corner::TopLeft,
+        Corner::TopRight,
+        Corner::BottomLeft,
+        Corner::BottomRight,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Corner::TopLeft => "Top Left",
+            Corner::TopRight => "Top Right",
+            Corner::BottomLeft => "Bottom Left",
+            Corner::BottomRight => "Bottom Right",
+        }
+    }
+
+    fn align(&self) -> egui::Align2 {
+        match self {
+            Corner::TopLeft => egui::Align2::LEFT_TOP,
+            Corner::TopRight => egui::Align2::RIGHT_TOP,
+            Corner::BottomLeft => egui::Align2::LEFT_BOTTOM,
+            Corner::BottomRight => egui::Align2::RIGHT_BOTTOM,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Select,
+    Bond,
+    Impulse,
+    Measure,
+}
+
+/// Something waiting to be added where the viewport is clicked, drawn as a ghost under the
+/// cursor until then
+#[derive(Clone, Copy, PartialEq)]
+enum Placement {
+    Particle(Element),
+    Water,
+    Lattice,
+    Emitter,
+}
+
+/// Where the preferences are saved when the app closes and loaded from when it starts,
+/// in the per-user config directory
+fn settings_path() -> Option<std::path::PathBuf> {
+    Settings::path("atomsimulation", "settings.txt")
+}
+
+/// Older versions saved the settings in the working directory, they're still loaded from
+/// here until the settings are saved to `settings_path`
+const LEGACY_SETTINGS_PATH: &str = "settings.txt";
+
+/// Every keyboard shortcut for the Help window, as the keys and what they do
+const SHORTCUTS: [(&str, &str); 11] = [
+    ("I", "Toggle the Info window"),
+    ("F1", "Toggle this window"),
+    ("H", "Toggle the HUD"),
+    ("M", "Toggle the minimap"),
+    ("G", "Toggle the spatial hash grid"),
+    ("+ / -", "Zoom in and out"),
+    ("Ctrl+D", "Duplicate the selected object"),
+    ("Esc", "Cancel placing an object"),
+    ("C", "Focus the camera on the selected object"),
+    ("Right Drag", "Pan the camera"),
+    ("Scroll", "Zoom"),
+];
+
+struct LatticeSettings {
+    rows: usize,
+    cols: usize,
+    spacing: f32,
+    element: Element,
+    bond_neighbors: bool,
+    /// Whether the anchors are unfrozen once a lattice has relaxed
+    unfreeze_after_relax: bool,
+}
+
+impl LatticeSettings {
+    /// Distance between the outermost particles
+    fn size(&self) -> Vector2<f32> {
+        Vector2 {
+            x: (self.cols - 1) as f32,
+            y: (self.rows - 1) as f32,
+        } * self.spacing
+    }
+}
+
+/// A lattice that's still relaxing after "Build and Relax", with its outer ring frozen in
+/// place and the whole scene cooled until the lattice stops moving
+struct LatticeRelax {
+    /// `Particle::id` of every particle in the lattice
+    particles: HashSet<u64>,
+    /// `Particle::id` of the frozen outer ring
+    anchors: HashSet<u64>,
+    /// What the cooling rate goes back to once the lattice has relaxed
+    previous_cooling_rate: f32,
+    equilibrium: EquilibriumDetector,
+}
+
+impl LatticeRelax {
+    /// Strong enough to stop the bonds oscillating within a few seconds
+    const COOLING_RATE: f32 = 2.0;
+    /// Per lattice particle, the lattice has relaxed once it stays below this
+    const SETTLED_ENERGY: f32 = 1e-4;
+
+    /// Forgets a relaxing lattice when the scene is replaced, giving back the cooling rate
+    fn cancel(relax: &mut Option<LatticeRelax>, config: &mut SimulationConfig) {
+        if let Some(relax) = relax.take() {
+            config.cooling_rate = relax.previous_cooling_rate;
+        }
+    }
+}
+
+/// Slows the simulation down for a while after a hard collision or a reaction
+struct SlowMotion {
+    enabled: bool,
+    /// Smallest collision impulse that triggers the slow motion
+    threshold: f32,
+    /// Seconds of real time it takes to ramp back to full speed
+    duration: f32,
+    remaining: f32,
+}
+
+impl SlowMotion {
+    /// How much the time step is slowed down at the start of the slow motion
+    const SLOWEST: f32 = 0.1;
+
+    fn trigger(&mut self, report: &StepReport) {
+        if self.enabled
+            && (!report.new_bonds.is_empty()
+                || report
+                    .collisions
+                    .iter()
+                    .any(|collision| collision.impulse >= self.threshold))
+        {
+            self.remaining = self.duration;
+        }
+    }
+
+    fn time_factor(&self) -> f32 {
+        if self.remaining <= 0.0 {
+            return 1.0;
+        }
+        let progress = 1.0 - self.remaining / self.duration;
+        Self::SLOWEST + (1.0 - Self::SLOWEST) * progress
+    }
+}
+
+/// Spawns many random particles and compares the frame rate before and after
+struct StressTest {
+    count: usize,
+    seed: u64,
+    /// Smoothed so that a single slow frame doesn't skew the comparison
+    average_frame_time: f32,
+    fps_before: Option<f32>,
+    fps_after: Option<f32>,
+    /// Seconds since the particles were spawned, `None` when no test is running
+    elapsed: Option<f32>,
+    measured_frames: usize,
+    measured_time: f32,
+}
+
+impl StressTest {
+    /// Seconds to wait after spawning for the frame rate to stabilize
+    const SETTLE_TIME: f32 = 2.0;
+    const MEASURE_TIME: f32 = 3.0;
+
+    fn start(&mut self) {
+        self.fps_before = Some(1.0 / self.average_frame_time);
+        self.fps_after = None;
+        self.elapsed = Some(0.0);
+        self.measured_frames = 0;
+        self.measured_time = 0.0;
+    }
+
+    fn update(&mut self, frame_time: f32) {
+        self.average_frame_time += (frame_time - self.average_frame_time) * 0.05;
+        let Some(elapsed) = &mut self.elapsed else {
+            return;
+        };
+        *elapsed += frame_time;
+        if *elapsed >= Self::SETTLE_TIME {
+            self.measured_frames += 1;
+            self.measured_time += frame_time;
+            if self.measured_time >= Self::MEASURE_TIME {
+                self.fps_after = Some(self.measured_frames as f32 / self.measured_time);
+                self.elapsed = None;
+            }
+        }
+    }
+}
+
+/// Tells clicks from drags by how far the pointer moved while a button was held, egui's
+/// own distance is fixed and also turns slow clicks into drags
+struct ClickFilter {
+    /// How many points the pointer can move while pressed and still count as a click
+    threshold: f32,
+    /// The primary and secondary presses that started on the viewport
+    presses: [Option<Press>; 2],
+}
+
+#[derive(Clone, Copy)]
+struct Press {
+    origin: egui::Pos2,
+    /// Whether the pointer has moved past the threshold since the press
+    dragging: bool,
+    /// Whether the pointer moved past the threshold this frame
+    drag_started: bool,
+}
+
+impl ClickFilter {
+    const BUTTONS: [egui::PointerButton; 2] =
+        [egui::PointerButton::Primary, egui::PointerButton::Secondary];
+
+    /// Updates the presses and replaces the clicks of the response with clicks that stayed
+    /// within the threshold
+    fn update(&mut self, ctx: &egui::Context, response: &mut egui::Response) {
+        for (press, button) in self.presses.iter_mut().zip(Self::BUTTONS) {
+            let (pressed, released, position) = ctx.input(|input| {
+                (
+                    input.pointer.button_pressed(button),
+                    input.pointer.button_released(button),
+                    input.pointer.interact_pos(),
+                )
+            });
+            if pressed && response.is_pointer_button_down_on() {
+                *press = position.map(|origin| Press {
+                    origin,
+                    dragging: false,
+                    drag_started: false,
+                });
+            }
+            response.clicked[button as usize] = false;
+            let Some(current) = press else {
+                continue;
+            };
+            current.drag_started = false;
+            if let Some(position) = position {
+                if !current.dragging && current.origin.distance(position) > self.threshold {
+                    current.dragging = true;
+                    current.drag_started = true;
+                }
+            }
+            if released || !ctx.input(|input| input.pointer.button_down(button)) {
+                response.clicked[button as usize] = released && !current.dragging;
+                *press = None;
+            }
+        }
+    }
+
+    fn press(&self, button: egui::PointerButton) -> Option<Press> {
+        Self::BUTTONS
+            .iter()
+            .position(|&other| other == button)
+            .and_then(|index| self.presses[index])
+    }
+
+    fn dragged_by(&self, button: egui::PointerButton) -> bool {
+        self.press(button).is_some_and(|press| press.dragging)
+    }
+
+    /// Where the drag started if the pointer moved past the threshold this frame
+    fn drag_started_by(&self, button: egui::PointerButton) -> Option<egui::Pos2> {
+        self.press(button)
+            .filter(|press| press.drag_started)
+            .map(|press| press.origin)
+    }
+}
+
+/// The recent positions of every particle along with the speed it had there, indexed like
+/// the particles
+struct Trails {
+    points: Vec<VecDeque<(Vector2<f32>, f32)>>,
+    /// Speed at which a trail is fully red
+    max_speed: f32,
+}
+
+impl Trails {
+    /// Points kept per particle when there are few particles
+    const LENGTH: usize = 60;
+    /// Points kept over all the particles, so huge scenes get shorter trails
+    const MAX_POINTS: usize = 20_000;
+
+    fn record(&mut self, particles: &[Particle]) {
+        self.points.resize_with(particles.len(), VecDeque::new);
+        let length = Self::LENGTH.min(Self::MAX_POINTS / particles.len().max(1));
+        for (trail, particle) in self.points.iter_mut().zip(particles) {
+            trail.push_back((particle.position, particle.velocity.magnitude()));
+            while trail.len() > length {
+                trail.pop_front();
+            }
+        }
+    }
+
+    /// Keeps the other trails lined up with their particles
+    fn remove(&mut self, i: usize) {
+        if i < self.points.len() {
+            self.points.remove(i);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.points.clear();
+    }
+}
+
+/// Blue when still through green and yellow to red at `fraction` 1
+fn speed_color(fraction: f32) -> egui::Color32 {
+    let fraction = fraction.clamp(0.0, 1.0);
+    let [r, g, b] = if fraction < 0.5 {
+        let t = fraction * 2.0;
+        [0.0, t, 1.0 - t]
+    } else {
+        let t = fraction * 2.0 - 1.0;
+        [t, 1.0 - t * 0.5, 0.0]
+    };
+    egui::Color32::from_rgb((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8)
+}
+
+/// Ramps the cooling up from nothing, so the system has time to settle as it cools and
+/// ends up in a lower energy arrangement than cooling it all at once would
+struct Anneal {
+    /// The cooling rate reached at the end of the ramp, which is kept afterwards
+    rate: f32,
+    duration: f32,
+    /// Simulated seconds since the ramp started
+    elapsed: f32,
+}
+
+impl Anneal {
+    fn cooling_rate(&self) -> f32 {
+        self.rate * (self.elapsed / self.duration).min(1.0)
+    }
+}
+
+/// Simulated seconds per real second, averaged over a short window so it doesn't flicker.
+/// It's the product of everything that scales time: the time scale, fixed dt, slow motion,
+/// fast forward and frames too slow to keep up
+struct RealTimeFactor {
+    simulated: f32,
+    elapsed: f32,
+    factor: f32,
+}
+
+impl RealTimeFactor {
+    /// Real seconds averaged over
+    const WINDOW: f32 = 0.5;
+
+    fn update(&mut self, frame_time: f32) {
+        self.elapsed += frame_time;
+        if self.elapsed >= Self::WINDOW {
+            self.factor = self.simulated / self.elapsed;
+            self.simulated = 0.0;
+            self.elapsed = 0.0;
+        }
+    }
+}
+
+struct LoggedCollision {
+    /// Simulation time of the step the collision happened in
+    time: f32,
+    event: CollisionEvent,
+}
+
+struct Recording {
+    directory: std::path::PathBuf,
+    frames: usize,
+}
+
+struct FastForward {
+    target_steps: usize,
+    steps_done: usize,
+    start_time: std::time::Instant,
+}
+
+struct Replay {
+    snapshots: VecDeque<Scene>,
+    interval: usize,
+    frames_since_snapshot: usize,
+    position: usize,
+}
+
+impl Replay {
+    const MAX_SNAPSHOTS: usize = 300;
+
+    fn record(&mut self, scene: &Scene) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+
+        // Scrubbing back and resuming discards the snapshots that came after
+        self.snapshots.truncate(self.position + 1);
+        self.snapshots.push_back(scene.clone());
+        while self.snapshots.len() > Self::MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.position = self.snapshots.len() - 1;
+    }
+}
+
+/// eframe can't reconfigure the surface once it's running, so the present mode is picked with
+/// `--present-mode=<name>` and changing it from the UI relaunches the app
+const PRESENT_MODES: [(wgpu::PresentMode, &str, &str); 5] = [
+    (
+        wgpu::PresentMode::AutoNoVsync,
+        "auto-no-vsync",
+        "Immediate or Mailbox where available, otherwise Fifo",
+    ),
+    (
+        wgpu::PresentMode::AutoVsync,
+        "auto-vsync",
+        "FifoRelaxed where available, otherwise Fifo",
+    ),
+    (
+        wgpu::PresentMode::Fifo,
+        "fifo",
+        "VSync, available on every platform",
+    ),
+    (
+        wgpu::PresentMode::Mailbox,
+        "mailbox",
+        "VSync without blocking the app, mostly available with Vulkan and DirectX 12",
+    ),
+    (
+        wgpu::PresentMode::Immediate,
+        "immediate",
+        "No VSync and may tear, unavailable on the web",
+    ),
+];
+
+/// Auto modes fall back to Fifo by themselves and every surface supports Fifo. wgpu panics
+/// when it configures a surface with any other mode the surface doesn't support.
+const ALWAYS_SUPPORTED_PRESENT_MODES: [wgpu::PresentMode; 3] = [
+    wgpu::PresentMode::AutoNoVsync,
+    wgpu::PresentMode::AutoVsync,
+    wgpu::PresentMode::Fifo,
+];
+
+/// Makes the process print the names of the supported present modes and exit
+const LIST_PRESENT_MODES_ARG: &str = "--list-present-modes";
+
+/// Asks a child process for `rendering::supported_present_modes`, since there's no access to
+/// the surface eframe creates. Only the modes that always work are returned if that fails.
+fn probe_present_modes() -> Vec<wgpu::PresentMode> {
+    let mut modes = ALWAYS_SUPPORTED_PRESENT_MODES.to_vec();
+    let output = std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .arg(LIST_PRESENT_MODES_ARG)
+            // Just the panic message if the probe can't open a window
+            .env("RUST_BACKTRACE", "0")
+            .output()
+    });
+    match output {
+        Ok(output) if output.status.success() => {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                if let Some(&(mode, _, _)) =
+                    PRESENT_MODES.iter().find(|&&(_, name, _)| name == line)
+                {
+                    if !modes.contains(&mode) {
+                        modes.push(mode);
+                    }
+                }
+            }
+        }
+        Ok(output) => eprintln!(
+            "Failed to find the supported present modes: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(error) => eprintln!("Failed to find the supported present modes: {error}"),
+    }
+    modes
+}
+
+/// The present modes offered in the UI, probed in the background unless they were needed
+/// at startup
+enum SupportedPresentModes {
+    Probing(std::thread::JoinHandle<Vec<wgpu::PresentMode>>),
+    Known(Vec<wgpu::PresentMode>),
+}
+
+impl SupportedPresentModes {
+    fn get(&mut self) -> &[wgpu::PresentMode] {
+        if matches!(self, Self::Probing(probe) if probe.is_finished()) {
+            let Self::Probing(probe) = std::mem::replace(self, Self::Known(vec![])) else {
+                unreachable!()
+            };
+            *self = Self::Known(
+                probe
+                    .join()
+                    .unwrap_or_else(|_| ALWAYS_SUPPORTED_PRESENT_MODES.to_vec()),
+            );
+        }
+        match self {
+            Self::Probing(_) => &ALWAYS_SUPPORTED_PRESENT_MODES,
+            Self::Known(modes) => modes,
+        }
+    }
+}
+
+/// Where the scene is kept while "Restart to Apply" relaunches the app, passed to the new
+/// process with `--scene=<path>`
+fn relaunch_scene_path() -> std::path::PathBuf {
+    std::env::temp_dir().join("atomsimulation_relaunch_scene.txt")
+}
+
+/// Creates the first `take_<n>` directory in `parent` that doesn't exist yet, so a new
+/// recording never overwrites the frames of an older one
+fn create_take_directory(parent: &str) -> std::io::Result<std::path::PathBuf> {
+    std::fs::create_dir_all(parent)?;
+    let mut take = 1;
+    loop {
+        let directory = std::path::Path::new(parent).join(format!("take_{take:03}"));
+        match std::fs::create_dir(&directory) {
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => take += 1,
+            result => return result.map(|()| directory),
+        }
+    }
+}
+
+fn present_mode_name(present_mode: wgpu::PresentMode) -> &'static str {
+    PRESENT_MODES
+        .iter()
+        .find(|&&(mode, _, _)| mode == present_mode)
+        .map_or("unknown", |&(_, name, _)| name)
+}
+
+struct App {
+    /// Set when the renderer couldn't be created, in which case only an error is shown
+    render_error: Option<&'static str>,
+    present_mode: wgpu::PresentMode,
+    /// The present mode picked in the UI, applied by relaunching
+    requested_present_mode: wgpu::PresentMode,
+    supported_present_modes: SupportedPresentModes,
+    /// Why the shaders didn't compile at startup or on the last reload
+    shader_error: Option<String>,
+    last_frame_time: Option<std::time::Instant>,
+    info_window_open: bool,
+    help_window_open: bool,
+    collision_log_window_open: bool,
+    speed_histogram_window_open: bool,
+    speed_histogram_bins: usize,
+    /// Only particles of this element are counted, all of them when `None`
+    speed_histogram_element: Option<Element>,
+    /// Shown instead of the live histogram while the histogram is frozen
+    frozen_speed_histogram: Option<SpeedHistogram>,
+    log_collisions: bool,
+    collision_log: VecDeque<LoggedCollision>,
+    /// Seconds simulated since the app started
+    simulation_time: f32,
+    real_time_factor: RealTimeFactor,
+    emitters_window_open: bool,
+    emitters: Vec<Emitter>,
+    /// Every placed emitter gets its own seed so that emitters side by side don't spray
+    /// their particles in lockstep
+    next_emitter_seed: u64,
+    /// Emitters stop spawning once the scene has this many particles
+    max_particles: usize,
+    elements_window_open: bool,
+    lattice_window_open: bool,
+    lattice: LatticeSettings,
+    lattice_relax: Option<LatticeRelax>,
+    show_particles: bool,
+    show_bonds: bool,
+    show_walls: bool,
+    /// Everything drawn on top of the scene, like labels, trails, the HUD and the minimap
+    show_overlays: bool,
+    show_center_of_mass: bool,
+    /// Draw a translucent cloud around every particle, `van_der_waals_scale` times its radius,
+    /// which doesn't change what it collides with
+    show_van_der_waals: bool,
+    van_der_waals_scale: f32,
+    show_particle_ids: bool,
+    show_element_labels: bool,
+    show_symbols: bool,
+    use_sprites: bool,
+    show_minimap: bool,
+    show_scale_bar: bool,
+    /// Draw where the particles have been, colored by how fast they were moving
+    show_speed_trails: bool,
+    trails: Trails,
+    show_spatial_hash: bool,
+    show_bond_stress: bool,
+    /// Relative change in length at which a bond is fully red or blue
+    bond_stress_scale: f32,
+    /// Draw stronger bonds thicker
+    show_bond_strength: bool,
+    /// Bonds are `Bond::strength` times this many pixels wide
+    bond_thickness_scale: f32,
+    energy_filter: EnergyFilter,
+    energy_threshold: f32,
+    /// The spatial hash cell size of the last step, so the overlay matches what the physics used
+    last_cell_size: f32,
+    /// Keep gravity pointing to the bottom of the screen, keeping only its strength
+    gravity_follows_screen: bool,
+    /// Width in pixels of the faded rim of the particles
+    edge_softness: f32,
+    /// Keep the camera moving after panning or zooming
+    camera_inertia: bool,
+    /// How many zoom steps a notch of the scroll wheel or a +/- press zooms by
+    zoom_sensitivity: f32,
+    click_filter: ClickFilter,
+    show_hud: bool,
+    hud_corner: Corner,
+    selected_object: Option<SelectedObject>,
+    /// Ids of the particles the inspector already warned about having more bonds than
+    /// electrons to share, so the warning isn't printed every frame
+    over_attached_warned: HashSet<u64>,
+    /// The particle the camera stays centered on, even once something else is selected
+    followed_particle: Option<usize>,
+    /// Seconds the camera takes to catch up with the followed particle, 0 sticks to it
+    follow_smoothing: f32,
+    /// Rectangles shift clicked with the select tool to be edited together
+    selected_rectangles: Vec<usize>,
+    /// The object that was right clicked to open the context menu
+    context_object: Option<SelectedObject>,
+    tool: Tool,
+    placement: Option<Placement>,
+    /// The first particle clicked with the bond tool
+    bond_start: Option<usize>,
+    impulse_radius: f32,
+    /// Two opposite corners of the region dragged out with the measure tool
+    measure_region: Option<[Vector2<f32>; 2]>,
+    impulse_strength: f32,
+    /// Where and when the impulse tool was last used, for the ring effect
+    impulse_ring: Option<(Vector2<f32>, std::time::Instant)>,
+    paused: bool,
+    pause_at_equilibrium: bool,
+    equilibrium: EquilibriumDetector,
+    /// Whether the pause came from reaching equilibrium, to tell the user why it stopped
+    paused_at_equilibrium: bool,
+    /// Particles that exploded to NaN or infinity, the simulation is paused while there are any
+    non_finite_particles: Vec<usize>,
+    step_requested: bool,
+    use_fixed_dt: bool,
+    fixed_dt: f32,
+    time_scale: usize,
+    slow_motion: SlowMotion,
+    stress_test: StressTest,
+    /// Presets get normally distributed velocities with this standard deviation, `None`
+    /// keeps the velocities of the preset
+    preset_velocity_std_dev: Option<f32>,
+    preset_velocity_seed: u64,
+    fast_forward: Option<FastForward>,
+    /// Writes a PNG of every frame the simulation steps while set
+    recording: Option<Recording>,
+    recording_size: [u32; 2],
+    /// Where the last recording went, or why it stopped
+    recording_status: Option<String>,
+    fast_forward_steps: usize,
+    /// Drawing the scene every frame while fast-forwarding slows it down
+    show_scene_while_fast_forwarding: bool,
+    tunneled_particles: usize,
+    absorbed_particles: usize,
+    anneal: Option<Anneal>,
+    /// Simulated seconds an anneal takes to reach the cooling rate
+    anneal_duration: f32,
+    /// Kinetic energy released by reactions since the reset, to check it against the change
+    /// in kinetic energy
+    reaction_energy: f32,
+    /// Total kinetic energy at the last reset of `reaction_energy`
+    initial_kinetic_energy: f32,
+    /// The result of the last save or load, shown next to the buttons
+    scene_file_status: Option<String>,
+    /// The wall contacts from last frame's steps, collected while they're shown
+    contacts: Vec<Contact>,
+    /// The most collision iterations any step used last frame
+    last_iterations: usize,
+    replay: Replay,
+    config: SimulationConfig,
+    camera: Camera,
+    scene: Scene,
+}
+
+impl App {
+    fn new(
+        cc: &eframe::CreationContext,
+        present_mode: wgpu::PresentMode,
+        supported_present_modes: SupportedPresentModes,
+    ) -> App {
+        let (render_error, shader_error) = match create_render_state(cc) {
+            Ok(shader_error) => (None, shader_error),
+            Err(error) => (Some(error), None),
+        };
+        if let Some(ref error) = shader_error {
+            eprintln!("Failed to compile the shaders: {error}");
+        }
+
+        // The initial scene can be changed without recompiling by writing it to this file,
+        // see `scene_toml::load_scene_toml` for what it can contain
+        const STARTUP_SCENE_PATH: &str = "scene.toml";
+        // A relaunch passes on the scene it was showing instead, saved with `scene_file`
+        let relaunch_scene =
+            std::env::args().find_map(|arg| arg.strip_prefix("--scene=").map(str::to_owned));
+        let scene_path = relaunch_scene.as_deref().unwrap_or(STARTUP_SCENE_PATH);
+        let mut custom_elements = vec![];
+        let loaded = std::fs::read_to_string(scene_path).map(|text| match relaunch_scene {
+            Some(_) => scene_file::load_scene(&text, &mut custom_elements)
+                .map_err(|error| error.to_string()),
+            None => scene_toml::load_scene_toml(&text).map_err(|error| error.to_string()),
+        });
+        let (scene, scene_file_status) = match loaded {
+            Ok(Ok(scene)) => (scene, Some(format!("Loaded {scene_path}"))),
+            Ok(Err(error)) => {
+                let status = format!("Failed to load {scene_path}: {error}");
+                eprintln!("{status}");
+                (presets::demo(), Some(status))
+            }
+            Err(error)
+                if error.kind() == std::io::ErrorKind::NotFound && relaunch_scene.is_none() =>
+            {
+                (presets::demo(), None)
+            }
+            Err(error) => {
+                let status = format!("Failed to read {scene_path}: {error}");
+                eprintln!("{status}");
+                (presets::demo(), Some(status))
+            }
+        };
+
+        let mut app = App {
+            render_error,
+            present_mode,
+            requested_present_mode: present_mode,
+            supported_present_modes,
+            shader_error,
+            last_frame_time: None,
+            info_window_open: true,
+            help_window_open: false,
+            collision_log_window_open: false,
+            speed_histogram_window_open: false,
+            speed_histogram_bins: 20,
+            speed_histogram_element: None,
+            frozen_speed_histogram: None,
+            log_collisions: false,
+            collision_log: VecDeque::new(),
+            simulation_time: 0.0,
+            real_time_factor: RealTimeFactor {
+                simulated: 0.0,
+                elapsed: 0.0,
+                factor: 0.0,
+            },
+            emitters_window_open: false,
+            emitters: vec![],
+            next_emitter_seed: 0,
+            max_particles: 2000,
+            elements_window_open: false,
+            lattice_window_open: false,
+            lattice: LatticeSettings {
+                rows: 5,
+                cols: 5,
+                spacing: 1.5,
+                element: Element::Hydrogen,
+                bond_neighbors: true,
+                unfreeze_after_relax: true,
+            },
+            lattice_relax: None,
+            show_particles: true,
+            show_bonds: true,
+            show_walls: true,
+            show_overlays: true,
+            show_center_of_mass: false,
+            show_van_der_waals: false,
+            van_der_waals_scale: 1.5,
+            show_particle_ids: false,
+            show_element_labels: false,
+            show_symbols: false,
+            use_sprites: false,
+            show_minimap: false,
+            show_scale_bar: false,
+            show_speed_trails: false,
+            trails: Trails {
+                points: vec![],
+                max_speed: 10.0,
+            },
+            show_spatial_hash: false,
+            show_bond_stress: false,
+            bond_stress_scale: 0.2,
+            show_bond_strength: false,
+            bond_thickness_scale: 1.0,
+            energy_filter: EnergyFilter::Off,
+            energy_threshold: 1.0,
+            last_cell_size: 0.0,
+            gravity_follows_screen: false,
+            edge_softness: 1.0,
+            camera_inertia: false,
+            zoom_sensitivity: 1.0,
+            click_filter: ClickFilter {
+                threshold: 6.0,
+                presses: [None; 2],
+            },
+            show_hud: false,
+            hud_corner: Corner::TopLeft,
+            selected_object: None,
+            over_attached_warned: HashSet::new(),
+            followed_particle: None,
+            follow_smoothing: 0.1,
+            selected_rectangles: vec![],
+            context_object: None,
+            tool: Tool::Select,
+            placement: None,
+            bond_start: None,
+            measure_region: None,
+            impulse_radius: 5.0,
+            impulse_strength: 1.0,
+            impulse_ring: None,
+            paused: false,
+            pause_at_equilibrium: false,
+            equilibrium: EquilibriumDetector::new(0.01, 120),
+            paused_at_equilibrium: false,
+            non_finite_particles: vec![],
+            step_requested: false,
+            use_fixed_dt: false,
+            fixed_dt: 1.0 / 60.0,
+            time_scale: 1,
+            slow_motion: SlowMotion {
+                enabled: false,
+                threshold: 2.0,
+                duration: 2.0,
+                remaining: 0.0,
+            },
+            preset_velocity_std_dev: None,
+            preset_velocity_seed: 0,
+            stress_test: StressTest {
+                count: 1000,
+                seed: 0,
+                average_frame_time: 1.0 / 60.0,
+                fps_before: None,
+                fps_after: None,
+                elapsed: None,
+                measured_frames: 0,
+                measured_time: 0.0,
+            },
+            fast_forward: None,
+            recording: None,
+            recording_size: [1280, 720],
+            recording_status: None,
+            fast_forward_steps: 10000,
+            show_scene_while_fast_forwarding: false,
+            tunneled_particles: 0,
+            absorbed_particles: 0,
+            anneal: None,
+            anneal_duration: 20.0,
+            reaction_energy: 0.0,
+            initial_kinetic_energy: scene
+                .particles
+                .iter()
+                .map(|particle| particle.kinetic_energy(&custom_elements))
+                .sum(),
+            scene_file_status,
+            contacts: vec![],
+            last_iterations: 0,
+            replay: Replay {
+                snapshots: VecDeque::new(),
+                interval: 10,
+                frames_since_snapshot: 0,
+                position: 0,
+            },
+            config: SimulationConfig {
+                custom_elements,
+                ..Default::default()
+            },
+            camera: Camera {
+                position: Vector2 { x: 0.0, y: 0.0 },
+                zoom: 0.25,
+                velocity: Vector2::zero(),
+                zoom_velocity: 0.0,
+            },
+            scene,
+        };
+        // A missing or broken settings file just leaves the defaults
+        let text = settings_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .or_else(|| std::fs::read_to_string(LEGACY_SETTINGS_PATH).ok());
+        if let Some(text) = text {
+            app.load_settings(&Settings::parse(&text));
+        }
+        app
+    }
+
+    fn step(&mut self, dt: f32) {
+        // Only the most recent collisions are kept so the log doesn't grow forever
+        const MAX_LOGGED_COLLISIONS: usize = 500;
+
+        for emitter in &mut self.emitters {
+            emitter.tick(&mut self.scene, dt, self.max_particles);
+        }
+        let report = self.scene.step(&self.config, dt);
+        self.tunneled_particles += report.tunneled_particles;
+        self.reaction_energy += report.reaction_energy;
+        self.last_iterations = self.last_iterations.max(report.iterations);
+        self.slow_motion.trigger(&report);
+        if self.log_collisions {
+            for &event in &report.collisions {
+                if self.collision_log.len() == MAX_LOGGED_COLLISIONS {
+                    self.collision_log.pop_front();
+                }
+                self.collision_log.push_back(LoggedCollision {
+                    time: self.simulation_time,
+                    event,
+                });
+            }
+        }
+        self.simulation_time += dt;
+        self.real_time_factor.simulated += dt;
+        if let Some(ref mut anneal) = self.anneal {
+            anneal.elapsed += dt;
+            self.config.cooling_rate = anneal.cooling_rate();
+        }
+        if let Some(ref mut relax) = self.lattice_relax {
+            let kinetic_energy = self
+                .scene
+                .particles
+                .iter()
+                .filter(|particle| relax.particles.contains(&particle.id))
+                .map(|particle| particle.kinetic_energy(&self.config.custom_elements))
+                .sum();
+            if relax.equilibrium.update_energy(kinetic_energy) {
+                self.finish_lattice_relax();
+            }
+        }
+        if self.pause_at_equilibrium
+            && self
+                .equilibrium
+                .update(&self.scene.particles, &self.config.custom_elements)
+        {
+            self.paused = true;
+            self.paused_at_equilibrium = true;
+            self.equilibrium.reset();
+        }
+        self.absorbed_particles += report.absorbed_particles.len();
+        // In the order `Scene::step` removed them, since the later indices are shifted
+        // by the particles removed before them
+        for &i in report
+            .reacted_particles
+            .iter()
+            .chain(&report.absorbed_particles)
+            .chain(&report.expired_particles)
+        {
+            self.forget_removed_object(SelectedObject::Particle(i));
+        }
+        self.contacts.extend(report.contacts);
+        self.last_cell_size = report.cell_size;
+        if !report.non_finite_particles.is_empty() && self.non_finite_particles.is_empty() {
+            for &i in &report.non_finite_particles {
+                let particle = &self.scene.particles[i];
+                eprintln!(
+                    "Particle {i} ({}) became non-finite with position {:?} and velocity {:?} \
+                    after a step of dt {dt} at time scale {}, a large dt or time scale is the \
+                    usual cause",
+                    particle.symbol(&self.config.custom_elements),
+                    particle.position,
+                    particle.velocity,
+                    self.time_scale,
+                );
+            }
+            // Stop before the NaNs spread through the forces to every other particle
+            self.paused = true;
+            self.non_finite_particles = report.non_finite_particles;
+        }
+    }
+
+    /// How opaque the van der Waals clouds are drawn
+    const VAN_DER_WAALS_ALPHA: f32 = 0.2;
+
+    /// Everything the renderer needs to draw the scene through the camera
+    fn render_callback(&self, aspect: f32, hovered_particle: Option<usize>) -> RenderCallback {
+        RenderCallback {
+            camera: GpuCamera {
+                position: self.camera.position,
+                aspect,
+                zoom: self.camera.zoom,
+                edge_softness: self.edge_softness,
+            },
+            // Hidden categories aren't uploaded at all
+            circles: if !self.show_particles {
+                vec![]
+            } else {
+                let visible: Vec<(&Particle, u32, f32)> = self
+                    .scene
+                    .particles
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, particle)| {
+                        let outline = if particle.selected {
+                            GpuCircle::SELECTED_OUTLINE
+                        } else if hovered_particle == Some(i) {
+                            GpuCircle::HOVER_OUTLINE
+                        } else {
+                            GpuCircle::NO_OUTLINE
+                        };
+                        let alpha = if particle.kinetic_energy(&self.config.custom_elements)
+                            >= self.energy_threshold
+                        {
+                            1.0
+                        } else {
+                            match self.energy_filter {
+                                EnergyFilter::Off => 1.0,
+                                EnergyFilter::Dim => EnergyFilter::DIM_ALPHA,
+                                EnergyFilter::Hide => return None,
+                            }
+                        };
+                        Some((particle, outline, alpha))
+                    })
+                    .collect();
+                // Drawn first so they end up behind every solid circle
+                let clouds = visible.iter().filter(|_| self.show_van_der_waals).map(
+                    |&(particle, _, alpha)| GpuCircle {
+                        semi_axes: particle.semi_axes(&self.config.custom_elements)
+                            * self.van_der_waals_scale,
+                        sprite_index: GpuCircle::NO_SPRITE,
+                        ..self.gpu_circle(
+                            particle,
+                            GpuCircle::NO_OUTLINE,
+                            alpha * Self::VAN_DER_WAALS_ALPHA,
+                        )
+                    },
+                );
+                clouds
+                    .chain(visible.iter().map(|&(particle, outline, alpha)| {
+                        self.gpu_circle(particle, outline, alpha)
+                    }))
+                    .collect()
+            },
+            rectangles: if !self.show_walls {
+                vec![]
+            } else {
+                self.scene
+                    .rectangles
+                    .iter()
+                    .map(|rectangle| GpuRectangle {
+                        position: rectangle.position,
+                        color: rectangle.color,
+                        size: rectangle.size,
+                        alpha: 1.0,
+                    })
+                    .collect()
+            },
+        }
+    }
+
+    /// Flags the selected objects in the scene, which the highlights are drawn from
+    fn sync_selection(&mut self) {
+        let mut particles = vec![];
+        let mut rectangles = self.selected_rectangles.clone();
+        match self
+            .selected_object
+            .filter(|object| object.exists_in(&self.scene))
+        {
+            Some(SelectedObject::Particle(i)) => particles.push(i),
+            Some(SelectedObject::Rectangle(i)) => rectangles.push(i),
+            None => {}
+        }
+        self.scene.set_selection(&particles, &rectangles);
+    }
+
+    fn gpu_circle(&self, particle: &Particle, outline: u32, alpha: f32) -> GpuCircle {
+        GpuCircle {
+            position: particle.position,
+            color: particle.color(&self.config.custom_elements),
+            semi_axes: particle.semi_axes(&self.config.custom_elements),
+            orientation: particle.ellipse.map_or(0.0, |ellipse| ellipse.orientation),
+            sprite_index: if self.use_sprites {
+                particle.element.sprite_index()
+            } else {
+                GpuCircle::NO_SPRITE
+            },
+            outline,
+            alpha,
+        }
+    }
+
+    /// The particles `placement` adds centered on `position`, which are also drawn as its ghost
+    fn placed_scene(&self, placement: Placement, position: Vector2<f32>) -> Scene {
+        let mut scene = Scene {
+            particles: vec![],
+            bonds: BTreeMap::new(),
+            rectangles: vec![],
+        };
+        match placement {
+            Placement::Particle(element) => {
+                scene
+                    .particles
+                    .push(Particle::new(position, Vector2::zero(), element));
+            }
+            Placement::Water => presets::spawn_water(&mut scene, position, Vector2::zero()),
+            Placement::Lattice => {
+                let lattice = &self.lattice;
+                presets::spawn_lattice(
+                    &mut scene,
+                    position - lattice.size() * 0.5,
+                    lattice.rows,
+                    lattice.cols,
+                    lattice.spacing,
+                    lattice.element,
+                    lattice.bond_neighbors,
+                );
+            }
+            // Emitters don't add anything right away, their ghost is a particle they emit
+            Placement::Emitter => {
+                scene
+                    .particles
+                    .push(Particle::new(position, Vector2::zero(), Element::Hydrogen));
+            }
+        }
+        scene
+    }
+
+    fn place(&mut self, placement: Placement, position: Vector2<f32>) {
+        if placement == Placement::Emitter {
+            self.emitters.push(Emitter::new(
+                position,
+                Vector2::new(5.0, 0.0),
+                Element::Hydrogen,
+                self.next_emitter_seed,
+            ));
+            self.next_emitter_seed += 1;
+            return;
+        }
+        let placed = self.placed_scene(placement, position);
+        let first = self.scene.particles.len();
+        self.scene.particles.extend(placed.particles);
+        self.scene.bonds.extend(
+            placed
+                .bonds
+                .into_iter()
+                .map(|((a, b), bond)| ((a + first, b + first), bond)),
+        );
+    }
+
+    /// Builds a crystal in the middle of the view: spawns the lattice with its neighbors
+    /// bonded, freezes its outer ring as anchors and runs the simulation with extra cooling
+    /// until the lattice stops moving, see `finish_lattice_relax`
+    fn build_and_relax_lattice(&mut self) {
+        // Without bonds there's nothing to relax
+        self.lattice.bond_neighbors = true;
+        let first = self.scene.particles.len();
+        self.place(Placement::Lattice, self.camera.position);
+        let anchors = presets::lattice_boundary(self.lattice.rows, self.lattice.cols);
+        for &i in &anchors {
+            self.scene.particles[first + i].frozen = true;
+        }
+        let particles = &self.scene.particles[first..];
+        self.lattice_relax = Some(LatticeRelax {
+            particles: particles.iter().map(|particle| particle.id).collect(),
+            anchors: anchors.iter().map(|&i| particles[i].id).collect(),
+            previous_cooling_rate: self.config.cooling_rate,
+            equilibrium: EquilibriumDetector::new(
+                LatticeRelax::SETTLED_ENERGY * particles.len() as f32,
+                60,
+            ),
+        });
+        self.config.cooling_rate = LatticeRelax::COOLING_RATE;
+        self.paused = false;
+    }
+
+    /// Stops the cooling of a relaxing lattice, unfreezes its anchors if that was asked
+    /// for and pauses so the relaxed lattice can be looked at
+    fn finish_lattice_relax(&mut self) {
+        let Some(relax) = self.lattice_relax.take() else {
+            return;
+        };
+        self.config.cooling_rate = relax.previous_cooling_rate;
+        if self.lattice.unfreeze_after_relax {
+            for particle in &mut self.scene.particles {
+                if relax.anchors.contains(&particle.id) {
+                    particle.frozen = false;
+                }
+            }
+        }
+        self.paused = true;
+    }
+
+    fn start_stress_test(&mut self) {
+        // Fill the box spanned by the walls, keeping clear of the walls themselves
+        const INSET: f32 = 2.0;
+        let mut min = Vector2::new(f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        for rectangle in &self.scene.rectangles {
+            let half_size = rectangle.size * 0.5;
+            min.x = min.x.min(rectangle.position.x - half_size.x + INSET);
+            min.y = min.y.min(rectangle.position.y - half_size.y + INSET);
+            max.x = max.x.max(rectangle.position.x + half_size.x - INSET);
+            max.y = max.y.max(rectangle.position.y + half_size.y - INSET);
+        }
+        if min.x >= max.x || min.y >= max.y {
+            let half_size = Vector2::new(20.0, 12.0);
+            min = self.camera.position - half_size;
+            max = self.camera.position + half_size;
+        }
+        let count = self.stress_test.count.min(
+            self.max_particles
+                .saturating_sub(self.scene.particles.len()),
+        );
+        presets::spawn_random(
+            &mut self.scene,
+            &mut Rng::new(self.stress_test.seed),
+            count,
+            min,
+            max,
+            5.0,
+        );
+        self.stress_test.start();
+    }
+
+    /// The preferences kept between runs, everything about the scene is left to the scene files
+    fn save_settings(&self) -> Settings {
+        let mut settings = Settings::default();
+        settings.write("camera_x", self.camera.position.x);
+        settings.write("camera_y", self.camera.position.y);
+        settings.write("camera_zoom", self.camera.zoom);
+        settings.write("info_window_open", self.info_window_open);
+        settings.write("show_particles", self.show_particles);
+        settings.write("show_bonds", self.show_bonds);
+        settings.write("show_walls", self.show_walls);
+        settings.write("show_overlays", self.show_overlays);
+        settings.write("show_center_of_mass", self.show_center_of_mass);
+        settings.write("show_van_der_waals", self.show_van_der_waals);
+        settings.write("van_der_waals_scale", self.van_der_waals_scale);
+        settings.write("show_particle_ids", self.show_particle_ids);
+        settings.write("show_element_labels", self.show_element_labels);
+        settings.write("show_symbols", self.show_symbols);
+        settings.write("use_sprites", self.use_sprites);
+        settings.write("show_minimap", self.show_minimap);
+        settings.write("show_scale_bar", self.show_scale_bar);
+        settings.write("show_speed_trails", self.show_speed_trails);
+        settings.write("trail_max_speed", self.trails.max_speed);
+        settings.write("show_spatial_hash", self.show_spatial_hash);
+        settings.write("show_bond_stress", self.show_bond_stress);
+        settings.write("gravity_follows_screen", self.gravity_follows_screen);
+        settings.write("camera_inertia", self.camera_inertia);
+        settings.write("show_hud", self.show_hud);
+        settings.write("use_fixed_dt", self.use_fixed_dt);
+        settings.write("bond_stress_scale", self.bond_stress_scale);
+        settings.write("show_bond_strength", self.show_bond_strength);
+        settings.write("bond_thickness_scale", self.bond_thickness_scale);
+        settings.write("edge_softness", self.edge_softness);
+        settings.write("zoom_sensitivity", self.zoom_sensitivity);
+        settings.write("follow_smoothing", self.follow_smoothing);
+        settings.write("speed_histogram_bins", self.speed_histogram_bins);
+        settings.write("drag_threshold", self.click_filter.threshold);
+        settings.write("fixed_dt", self.fixed_dt);
+        settings.write("time_scale", self.time_scale);
+        settings.write("impulse_radius", self.impulse_radius);
+        settings.write("impulse_strength", self.impulse_strength);
+        settings.write("max_particles", self.max_particles);
+        settings
+    }
+
+    fn write_settings(&self) {
+        let Some(path) = settings_path() else {
+            eprintln!("Failed to save the settings: there's no config directory");
+            return;
+        };
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&path, self.save_settings().to_text()));
+        if let Err(error) = saved {
+            eprintln!("Failed to save the settings to {}: {error}", path.display());
+        }
+    }
+
+    fn load_settings(&mut self, settings: &Settings) {
+        settings.read_clamped("camera_x", &mut self.camera.position.x, f32::MIN..=f32::MAX);
+        settings.read_clamped("camera_y", &mut self.camera.position.y, f32::MIN..=f32::MAX);
+        settings.read_clamped(
+            "camera_zoom",
+            &mut self.camera.zoom,
+            Camera::MIN_ZOOM..=Camera::MAX_ZOOM,
+        );
+        settings.read("info_window_open", &mut self.info_window_open);
+        settings.read("show_particles", &mut self.show_particles);
+        settings.read("show_bonds", &mut self.show_bonds);
+        settings.read("show_walls", &mut self.show_walls);
+        settings.read("show_overlays", &mut self.show_overlays);
+        settings.read("show_center_of_mass", &mut self.show_center_of_mass);
+        settings.read("show_van_der_waals", &mut self.show_van_der_waals);
+        settings.read_clamped(
+            "van_der_waals_scale",
+            &mut self.van_der_waals_scale,
+            1.0..=4.0,
+        );
+        settings.read("show_particle_ids", &mut self.show_particle_ids);
+        settings.read("show_element_labels", &mut self.show_element_labels);
+        settings.read("show_symbols", &mut self.show_symbols);
+        settings.read("use_sprites", &mut self.use_sprites);
+        settings.read("show_minimap", &mut self.show_minimap);
+        settings.read("show_scale_bar", &mut self.show_scale_bar);
+        settings.read("show_speed_trails", &mut self.show_speed_trails);
+        settings.read_clamped(
+            "trail_max_speed",
+            &mut self.trails.max_speed,
+            0.01..=f32::MAX,
+        );
+        settings.read("show_spatial_hash", &mut self.show_spatial_hash);
+        settings.read("show_bond_stress", &mut self.show_bond_stress);
+        settings.read("gravity_follows_screen", &mut self.gravity_follows_screen);
+        settings.read("camera_inertia", &mut self.camera_inertia);
+        settings.read("show_hud", &mut self.show_hud);
+        settings.read("use_fixed_dt", &mut self.use_fixed_dt);
+        settings.read_clamped("bond_stress_scale", &mut self.bond_stress_scale, 0.01..=1.0);
+        settings.read("show_bond_strength", &mut self.show_bond_strength);
+        settings.read_clamped(
+            "bond_thickness_scale",
+            &mut self.bond_thickness_scale,
+            0.1..=4.0,
+        );
+        settings.read_clamped("edge_softness", &mut self.edge_softness, 0.0..=10.0);
+        settings.read_clamped("zoom_sensitivity", &mut self.zoom_sensitivity, 0.1..=5.0);
+        settings.read_clamped("follow_smoothing", &mut self.follow_smoothing, 0.0..=1.0);
+        settings.read_clamped(
+            "speed_histogram_bins",
+            &mut self.speed_histogram_bins,
+            1..=200,
+        );
+        settings.read_clamped(
+            "drag_threshold",
+            &mut self.click_filter.threshold,
+            0.0..=30.0,
+        );
+        settings.read_clamped("fixed_dt", &mut self.fixed_dt, 0.0001..=0.1);
+        settings.read_clamped("time_scale", &mut self.time_scale, 0..=20);
+        settings.read_clamped("impulse_radius", &mut self.impulse_radius, 0.0..=f32::MAX);
+        settings.read_clamped("impulse_strength", &mut self.impulse_strength, -10.0..=10.0);
+        settings.read("max_particles", &mut self.max_particles);
+    }
+
+    fn remove_object(&mut self, object: SelectedObject) {
+        match object {
+            SelectedObject::Particle(i) => {
+                self.scene.remove_particle(i);
+            }
+            SelectedObject::Rectangle(i) => {
+                self.scene.rectangles.remove(i);
+            }
+        }
+        self.forget_removed_object(object);
+    }
+
+    /// Shifts the selections to where the objects ended up after `object` was removed
+    fn forget_removed_object(&mut self, object: SelectedObject) {
+        // Following stops when the followed particle is removed
+        self.followed_particle =
+            self.followed_particle.and_then(|i| {
+                match SelectedObject::Particle(i).after_removal(object) {
+                    Some(SelectedObject::Particle(i)) => Some(i),
+                    _ => None,
+                }
+            });
+        if let SelectedObject::Particle(i) = object {
+            self.trails.remove(i);
+        }
+        self.selected_object = self
+            .selected_object
+            .and_then(|selected_object| selected_object.after_removal(object));
+        self.context_object = self
+            .context_object
+            .and_then(|context_object| context_object.after_removal(object));
+        self.selected_rectangles = std::mem::take(&mut self.selected_rectangles)
+            .into_iter()
+            .filter_map(
+                |i| match SelectedObject::Rectangle(i).after_removal(object) {
+                    Some(SelectedObject::Rectangle(i)) => Some(i),
+                    _ => None,
+                },
+            )
+            .collect();
+        self.bond_start =
+            self.bond_start.and_then(
+                |i| match SelectedObject::Particle(i).after_removal(object) {
+                    Some(SelectedObject::Particle(i)) => Some(i),
+                    _ => None,
+                },
+            );
+    }
+
+    /// Swaps in a scene that has nothing to do with the current one, forgetting everything
+    /// that pointed into the old one
+    fn replace_scene(&mut self, scene: Scene) {
+        self.scene = scene;
+        self.trails.clear();
+        self.replay.snapshots.clear();
+        self.replay.position = 0;
+        LatticeRelax::cancel(&mut self.lattice_relax, &mut self.config);
+        self.followed_particle = None;
+        self.selected_object = None;
+        self.selected_rectangles.clear();
+        self.context_object = None;
+        self.bond_start = None;
+    }
+
+    fn focus_selected(&mut self) {
+        if let Some(object) = self
+            .selected_object
+            .filter(|object| object.exists_in(&self.scene))
+        {
+            let (position, size) = object.extent(&self.scene, &self.config.custom_elements);
+            self.camera.focus(position, size);
+        }
+    }
+
+    /// Duplicates the object and selects the copy
+    fn duplicate_object(&mut self, object: SelectedObject) {
+        self.selected_object = Some(match object {
+            SelectedObject::Particle(i) => SelectedObject::Particle(
+                self.scene
+                    .duplicate_particle(i, &self.config.custom_elements),
+            ),
+            SelectedObject::Rectangle(i) => {
+                SelectedObject::Rectangle(self.scene.duplicate_rectangle(i))
+            }
+        });
+    }
+
+    fn show_context_menu(&mut self, ui: &mut egui::Ui, object: SelectedObject) {
+        if ui.button("Delete").clicked() {
+            self.remove_object(object);
+            ui.close_menu();
+            return;
+        }
+        match object {
+            SelectedObject::Particle(i) => {
+                let particle = &mut self.scene.particles[i];
+                if ui
+                    .button(if particle.frozen {
+                        "Unfreeze"
+                    } else {
+                        "Freeze"
+                    })
+                    .clicked()
+                {
+                    particle.frozen = !particle.frozen;
+                    particle.velocity = Vector2::zero();
+                    ui.close_menu();
+                }
+                if ui.button("Duplicate").clicked() {
+                    self.duplicate_object(object);
+                    ui.close_menu();
+                }
+                if ui.button("Zero Velocity").clicked() {
+                    self.scene.particles[i].velocity = Vector2::zero();
+                    ui.close_menu();
+                }
+            }
+            SelectedObject::Rectangle(_) => {
+                if ui.button("Duplicate").clicked() {
+                    self.duplicate_object(object);
+                    ui.close_menu();
+                }
+            }
+        }
+    }
+
+    fn show_minimap(&mut self, ui: &mut egui::Ui, viewport: egui::Rect, minimap_rect: egui::Rect) {
+        let mut min = Vector2 {
+            x: f32::INFINITY,
+            y: f32::INFINITY,
+        };
+        let mut max = -min;
+        for particle in &self.scene.particles {
+            let extent = Vector2 {
+                x: particle.radius(&self.config.custom_elements),
+                y: particle.radius(&self.config.custom_elements),
+            };
+            min = min.zip(particle.position - extent, f32::min);
+            max = max.zip(particle.position + extent, f32::max);
+        }
+        for rectangle in &self.scene.rectangles {
+            min = min.zip(rectangle.position - rectangle.size * 0.5, f32::min);
+            max = max.zip(rectangle.position + rectangle.size * 0.5, f32::max);
+        }
+        if min.x > max.x || min.y > max.y {
+            min = self.camera.position;
+            max = self.camera.position;
+        }
+
+        // Fit the bounds of the scene into the minimap without stretching it
+        let padding = 1.0;
+        let center = (min + max) * 0.5;
+        let scale = f32::min(
+            minimap_rect.width() / (max.x - min.x + padding * 2.0),
+            minimap_rect.height() / (max.y - min.y + padding * 2.0),
+        );
+        let to_minimap = |position: Vector2<f32>| {
+            minimap_rect.center() + egui::vec2(position.x - center.x, center.y - position.y) * scale
+        };
+
+        let response = ui.interact(minimap_rect, ui.id().with("Minimap"), egui::Sense::click());
+        if let Some(pointer_position) = response.interact_pointer_pos() {
+            if response.clicked() {
+                let offset = (pointer_position - minimap_rect.center()) / scale;
+                self.camera.position = center
+                    + Vector2 {
+                        x: offset.x,
+                        y: -offset.y,
+                    };
+            }
+        }
+
+        let painter = ui.painter_at(minimap_rect);
+        painter.rect(
+            minimap_rect,
+            0.0,
+            egui::Color32::from_rgba_unmultiplied(20, 20, 20, 220),
+            egui::Stroke::new(1.0, egui::Color32::GRAY),
+        );
+        for rectangle in &self.scene.rectangles {
+            let color = rectangle.color.map(|channel| (channel * 255.0) as u8);
+            painter.rect_filled(
+                egui::Rect::from_two_pos(
+                    to_minimap(rectangle.position - rectangle.size * 0.5),
+                    to_minimap(rectangle.position + rectangle.size * 0.5),
+                ),
+                0.0,
+                // Walls are usually almost black so they need to be brightened to show up
+                egui::Color32::from_rgb(color.x, color.y, color.z).gamma_multiply(4.0),
+            );
+        }
+        for particle in &self.scene.particles {
+            let color = particle
+                .color(&self.config.custom_elements)
+                .map(|channel| (channel * 255.0) as u8);
+            painter.circle_filled(
+                to_minimap(particle.position),
+                (particle.radius(&self.config.custom_elements) * scale).max(1.0),
+                egui::Color32::from_rgb(color.x, color.y, color.z),
+            );
+        }
+
+        let view_extent = Vector2 {
+            x: viewport.width() / viewport.height() / self.camera.zoom,
+            y: 1.0 / self.camera.zoom,
+        };
+        painter.rect_stroke(
+            egui::Rect::from_two_pos(
+                to_minimap(self.camera.position - view_extent),
+                to_minimap(self.camera.position + view_extent),
+            ),
+            0.0,
+            egui::Stroke::new(1.0, egui::Color32::WHITE),
+        );
+    }
+}
+
+impl eframe::App for App {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.write_settings();
+    }
+
+    fn update(&mut self, ctx: &eframe::egui::Context, frame: &mut eframe::Frame) {
+        if let Some(error) = self.render_error {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.centered_and_justified(|ui| {
+                    ui.label(format!(
+                        "{error}.\n\nThe simulation needs a GPU supported by wgpu (Vulkan, Metal, \
+                         DirectX 12 or OpenGL) to draw the particles. Virtual machines often \
+                         don't provide one.",
+                    ));
+                });
+            });
+            return;
+        }
+
+        // Large time steps make the particles teleport through each other and the walls
+        const MAX_DT: f32 = 1.0 / 30.0;
+        // Frames this long mean the app was stalled, e.g. while the window was being dragged
+        const STALL_TIME: f32 = 0.5;
+
+        let time = std::time::Instant::now();
+        let frame_time = time
+            .duration_since(self.last_frame_time.unwrap_or(time))
+            .as_secs_f32();
+        self.last_frame_time = Some(time);
+        if frame_time <= STALL_TIME {
+            self.stress_test.update(frame_time);
+        }
+        self.real_time_factor.update(frame_time);
+        let camera_dt = if frame_time > STALL_TIME {
+            0.0
+        } else {
+            frame_time.min(MAX_DT)
+        };
+        // Recordings always use the fixed dt so that the frames are evenly spaced in time
+        let dt = if self.use_fixed_dt || self.recording.is_some() {
+            self.fixed_dt
+        } else if frame_time > STALL_TIME {
+            0.0
+        } else {
+            frame_time.min(MAX_DT)
+        } * self.slow_motion.time_factor();
+        self.slow_motion.remaining = (self.slow_motion.remaining - camera_dt).max(0.0);
+
+        if self.gravity_follows_screen {
+            self.config.gravity = self.camera.screen_down() * self.config.gravity.magnitude();
+        }
+
+        if let Some(mut fast_forward) = self.fast_forward.take() {
+            // Only step for part of each frame so that the UI stays responsive
+            const STEP_BUDGET: std::time::Duration = std::time::Duration::from_millis(25);
+            self.last_iterations = 0;
+            self.contacts.clear();
+            while fast_forward.steps_done < fast_forward.target_steps
+                && time.elapsed() < STEP_BUDGET
+                && self.non_finite_particles.is_empty()
+            {
+                self.step(self.fixed_dt);
+                fast_forward.steps_done += 1;
+            }
+            self.replay.record(&self.scene);
+            if fast_forward.steps_done < fast_forward.target_steps
+                && self.non_finite_particles.is_empty()
+            {
+                self.fast_forward = Some(fast_forward);
+            }
+        } else if self.non_finite_particles.is_empty()
+            && (!self.paused || std::mem::take(&mut self.step_requested))
+        {
+            self.last_iterations = 0;
+            self.contacts.clear();
+            for _ in 0..self.time_scale {
+                self.step(dt);
+                if !self.non_finite_particles.is_empty() {
+                    break;
+                }
+            }
+            self.replay.record(&self.scene);
+            if self.show_speed_trails {
+                self.trails.record(&self.scene.particles);
+            }
+
+            if self.recording.is_some() {
+                let [width, height] = self.recording_size;
+                let callback = self.render_callback(width as f32 / height as f32, None);
+                let recording = self.recording.as_mut().unwrap();
+                let path = recording
+                    .directory
+                    .join(format!("frame_{:05}.png", recording.frames));
+                match render_to_png(
+                    frame.wgpu_render_state().unwrap(),
+                    &callback,
+                    width,
+                    height,
+                    &path,
+                ) {
+                    Ok(()) => recording.frames += 1,
+                    Err(error) => {
+                        self.recording_status = Some(format!(
+                            "Stopped recording, failed to write {}: {error}",
+                            path.display()
+                        ));
+                        self.recording = None;
+                    }
+                }
+            }
+        }
+        // Reaction products replace their reactants, which shifts the particle indices
+        let particle_count = self.scene.particles.len();
+        self.bond_start = self.bond_start.filter(|&i| i < particle_count);
+
+        if ctx.memory(|memory| memory.focus().is_none())
+            && ctx.input_mut(|input| input.consume_key(egui::Modifiers::COMMAND, egui::Key::D))
+        {
+            if let Some(object) = self.selected_object {
+                self.duplicate_object(object);
+            }
+        }
+        // Keep these in sync with `SHORTCUTS`, they're ignored while typing into a text field
+        if ctx.memory(|memory| memory.focus().is_none()) {
+            ctx.input(|input| {
+                if !input.modifiers.is_none() {
+                    return;
+                }
+                if input.key_pressed(egui::Key::I) {
+                    self.info_window_open = !self.info_window_open;
+                }
+                if input.key_pressed(egui::Key::F1) {
+                    self.help_window_open = !self.help_window_open;
+                }
+                if input.key_pressed(egui::Key::H) {
+                    self.show_hud = !self.show_hud;
+                }
+                if input.key_pressed(egui::Key::M) {
+                    self.show_minimap = !self.show_minimap;
+                }
+                if input.key_pressed(egui::Key::G) {
+                    self.show_spatial_hash = !self.show_spatial_hash;
+                }
+                if input.key_pressed(egui::Key::Escape) {
+                    self.placement = None;
+                }
+                if input.key_pressed(egui::Key::C) {
+                    self.focus_selected();
+                }
+            });
+        }
+
+        // The view is only known once the central panel is laid out
+        let mut walls_requested = false;
+        egui::TopBottomPanel::top("Menu").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                self.info_window_open |= ui.button("Info").clicked();
+                self.help_window_open |= ui.button("Help").clicked();
+                self.elements_window_open |= ui.button("Elements").clicked();
+                self.lattice_window_open |= ui.button("Lattice").clicked();
+                self.emitters_window_open |= ui.button("Emitters").clicked();
+                self.collision_log_window_open |= ui.button("Collision Log").clicked();
+                self.speed_histogram_window_open |= ui.button("Speeds").clicked();
+                ui.separator();
+                ui.selectable_value(&mut self.tool, Tool::Select, "Select");
+                ui.selectable_value(&mut self.tool, Tool::Bond, "Bond");
+                ui.selectable_value(&mut self.tool, Tool::Impulse, "Impulse");
+                ui.selectable_value(&mut self.tool, Tool::Measure, "Measure")
+                    .on_hover_text("Drag out a region to see statistics of the particles in it");
+                ui.separator();
+                ui.menu_button("Place", |ui| {
+                    for element in Element::ALL {
+                        if ui.button(element.name()).clicked() {
+                            self.placement = Some(Placement::Particle(element));
+                            ui.close_menu();
+                        }
+                    }
+                    if ui.button("Water Molecule").clicked() {
+                        self.placement = Some(Placement::Water);
+                        ui.close_menu();
+                    }
+                });
+                walls_requested = ui
+                    .button("Walls Around View")
+                    .on_hover_text("Adds four walls along the edges of what's visible")
+                    .clicked();
+                if self.placement.is_some() {
+                    ui.label("Click to place, hold Shift to keep placing, Esc to cancel");
+                }
+                if self.tool == Tool::Impulse {
+                    ui.separator();
+                    ui.label("Radius:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.impulse_radius)
+                            .speed(0.1)
+                            .clamp_range(0.0..=f32::INFINITY),
+                    );
+                    ui.label("Strength:");
+                    ui.add(egui::Slider::new(&mut self.impulse_strength, -10.0..=10.0));
+                }
+            });
+        });
+
+        if let Some(ref error) = self.shader_error {
+            let has_render_pipelines = frame
+                .wgpu_render_state()
+                .is_some_and(rendering::has_render_pipelines);
+            egui::Window::new("Shader Error").show(ctx, |ui| {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    if has_render_pipelines {
+                        "The shaders didn't compile, the scene is still drawn with the shaders \
+                         from before the reload"
+                    } else {
+                        "The shaders didn't compile so the scene can't be drawn, the simulation \
+                         keeps running"
+                    },
+                );
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| ui.monospace(error));
+            });
+        }
+
+        egui::Window::new("Help")
+            .open(&mut self.help_window_open)
+            .show(ctx, |ui| {
+                egui::Grid::new("Shortcuts Grid").show(ui, |ui| {
+                    for (keys, action) in SHORTCUTS {
+                        ui.monospace(keys);
+                        ui.label(action);
+                        ui.end_row();
+                    }
+                });
+            });
+
+        let mut restart_requested = false;
+        let mut stress_test_requested = false;
+        let mut replacement_scene = None;
+        egui::Window::new("Info")
+            .open(&mut self.info_window_open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("FPS: {:.3}", 1.0 / frame_time));
+                    ui.separator();
+                    ui.label(format!(
+                        "Real-Time Factor: {:.3}x",
+                        self.real_time_factor.factor
+                    ))
+                    .on_hover_text(
+                        "Simulated seconds per real second, below 1 is slower than real time",
+                    );
+                });
+                ui.label(format!("Frame Time: {:.3}ms", 1000.0 * frame_time));
+                ui.collapsing("Stress Test", |ui| {
+                    let stress_test = &mut self.stress_test;
+                    ui.horizontal(|ui| {
+                        ui.label("Particles:");
+                        ui.add(egui::DragValue::new(&mut stress_test.count));
+                        ui.label("Seed:");
+                        ui.add(egui::DragValue::new(&mut stress_test.seed));
+                    });
+                    stress_test_requested = ui
+                        .add_enabled(stress_test.elapsed.is_none(), egui::Button::new("Start"))
+                        .on_hover_text(
+                            "Spawns the particles inside the walls, up to the particle limit \
+                             in the Emitters window, and measures the FPS once it settles",
+                        )
+                        .clicked();
+                    if let Some(fps_before) = stress_test.fps_before {
+                        ui.label(format!("FPS Before: {fps_before:.1}"));
+                        match (stress_test.fps_after, stress_test.elapsed) {
+                            (_, Some(elapsed)) if elapsed < StressTest::SETTLE_TIME => {
+                                ui.label("Waiting for the FPS to settle...")
+                            }
+                            (_, Some(_)) => ui.label("Measuring..."),
+                            (Some(fps_after), None) => {
+                                ui.label(format!("FPS After: {fps_after:.1}"))
+                            }
+                            (None, None) => ui.label(""),
+                        };
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Present Mode: ");
+                    egui::ComboBox::from_id_source("Present Mode")
+                        .selected_text(present_mode_name(self.requested_present_mode))
+                        .show_ui(ui, |ui| {
+                            let supported = self.supported_present_modes.get();
+                            for (mode, name, description) in PRESENT_MODES {
+                                if supported.contains(&mode) {
+                                    ui.selectable_value(
+                                        &mut self.requested_present_mode,
+                                        mode,
+                                        name,
+                                    )
+                                    .on_hover_text(description);
+                                }
+                            }
+                        });
+                    if self.requested_present_mode != self.present_mode {
+                        restart_requested = ui
+                            .button("Restart to Apply")
+                            .on_hover_text(
+                                "Relaunches the simulation with the current scene, anything \
+                                 the scene file can't store is lost",
+                            )
+                            .clicked();
+                    }
+                });
+                #[cfg(feature = "shader-hot-reload")]
+                {
+                    if ui.button("Reload Shaders").clicked() {
+                        self.shader_error =
+                            rendering::reload_shaders(frame.wgpu_render_state().unwrap()).err();
+                    }
+                    if let Some(ref error) = self.shader_error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                }
+
+                // TODO: make this more accurate
+                // let mut energy = 0.0;
+                // for particle in &self.scene.particles {
+                //     energy += 0.5 * particle.mass(&self.config.custom_elements) * particle.velocity.magnitude2();
+                // }
+                // for bond in &self.scene.bonds {
+                //     let distance = self.scene.particles[bond.particle_a]
+                //         .position
+                //         .distance(self.scene.particles[bond.particle_b].position)
+                //         - (self.scene.particles[bond.particle_a].radius()
+                //             + self.scene.particles[bond.particle_b].radius());
+                //     energy += 0.5 * bond.strength(&self.scene.particles) * (distance * distance);
+                // }
+                // ui.label(format!("Energy: {:.3}", energy));
+
+                ui.horizontal(|ui| {
+                    ui.label("Draw:");
+                    ui.checkbox(&mut self.show_particles, "Particles");
+                    ui.checkbox(&mut self.show_bonds, "Bonds");
+                    ui.checkbox(&mut self.show_walls, "Walls");
+                    ui.checkbox(&mut self.show_overlays, "Overlays")
+                        .on_hover_text("Labels, trails, highlights, the HUD and the minimap");
+                });
+                ui.checkbox(&mut self.use_sprites, "Textured Particles");
+                ui.checkbox(&mut self.show_minimap, "Show Minimap");
+                ui.checkbox(&mut self.show_scale_bar, "Show Scale Bar");
+                ui.horizontal(|ui| {
+                    if ui
+                        .checkbox(&mut self.show_speed_trails, "Show Speed Trails")
+                        .changed()
+                    {
+                        self.trails.clear();
+                    }
+                    ui.add_enabled(
+                        self.show_speed_trails,
+                        egui::DragValue::new(&mut self.trails.max_speed)
+                            .speed(0.1)
+                            .clamp_range(0.01..=f32::INFINITY)
+                            .prefix("red at speed "),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Edge Softness:");
+                    ui.add(egui::Slider::new(&mut self.edge_softness, 0.0..=10.0).suffix(" px"))
+                        .on_hover_text("0 draws hard edges, which is slightly faster");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Zoom Sensitivity:");
+                    ui.add(
+                        egui::Slider::new(&mut self.zoom_sensitivity, 0.1..=5.0).logarithmic(true),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Drag Threshold:");
+                    ui.add(
+                        egui::Slider::new(&mut self.click_filter.threshold, 0.0..=30.0)
+                            .suffix(" px"),
+                    )
+                    .on_hover_text(
+                        "How far the pointer can move while pressed and still count as a click",
+                    );
+                });
+                if ui
+                    .checkbox(&mut self.camera_inertia, "Camera Inertia")
+                    .changed()
+                {
+                    self.camera.velocity = Vector2::zero();
+                    self.camera.zoom_velocity = 0.0;
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Follow Smoothing:");
+                    ui.add(egui::Slider::new(&mut self.follow_smoothing, 0.0..=1.0).suffix(" s"))
+                        .on_hover_text(
+                            "How long the camera takes to catch up with a followed particle",
+                        );
+                });
+                if let Some(i) = self.followed_particle {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Following particle {i}"));
+                        if ui.button("Stop").clicked() {
+                            self.followed_particle = None;
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_particle_ids, "Show IDs");
+                    ui.checkbox(&mut self.show_element_labels, "Show Elements");
+                    ui.checkbox(&mut self.show_symbols, "Show Symbols");
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_hud, "Show HUD");
+                    egui::ComboBox::from_id_source("HUD Corner")
+                        .selected_text(self.hud_corner.name())
+                        .show_ui(ui, |ui| {
+                            for corner in Corner::ALL {
+                                ui.selectable_value(&mut self.hud_corner, corner, corner.name());
+                            }
+                        });
+                });
+                if ui
+                    .checkbox(&mut self.config.collect_debug, "Show Wall Contacts")
+                    .changed()
+                {
+                    self.contacts.clear();
+                }
+                ui.checkbox(&mut self.show_spatial_hash, "Show Spatial Hash")
+                    .on_hover_text(format!(
+                        "Cells of {:.3} shaded by how many particles they hold",
+                        self.last_cell_size
+                    ));
+                ui.horizontal(|ui| {
+                    ui.label("Low Energy Particles:");
+                    egui::ComboBox::from_id_source("Energy Filter")
+                        .selected_text(self.energy_filter.name())
+                        .show_ui(ui, |ui| {
+                            for filter in EnergyFilter::ALL {
+                                ui.selectable_value(&mut self.energy_filter, filter, filter.name());
+                            }
+                        });
+                    ui.add_enabled(
+                        self.energy_filter != EnergyFilter::Off,
+                        egui::DragValue::new(&mut self.energy_threshold)
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::INFINITY)
+                            .prefix("energy < "),
+                    )
+                    .on_hover_text(
+                        "Particles with less kinetic energy than this are dimmed or hidden",
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_bond_stress, "Show Bond Stress")
+                        .on_hover_text("Compressed bonds are blue and stretched bonds are red");
+                    ui.add_enabled(
+                        self.show_bond_stress,
+                        egui::Slider::new(&mut self.bond_stress_scale, 0.01..=1.0)
+                            .logarithmic(true)
+                            .text("full color strain"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_bond_strength, "Show Bond Strength")
+                        .on_hover_text("Bonds that take more energy to break are drawn thicker");
+                    ui.add_enabled(
+                        self.show_bond_strength,
+                        egui::Slider::new(&mut self.bond_thickness_scale, 0.1..=4.0)
+                            .logarithmic(true)
+                            .text("pixels per strength"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_van_der_waals, "Show Van der Waals Radius")
+                        .on_hover_text(
+                            "Draws a translucent cloud around every particle, the collisions \
+                             still use the solid circle",
+                        );
+                    ui.add_enabled(
+                        self.show_van_der_waals,
+                        egui::Slider::new(&mut self.van_der_waals_scale, 1.0..=4.0)
+                            .text("times the radius"),
+                    );
+                });
+                ui.checkbox(&mut self.show_center_of_mass, "Show Center of Mass");
+                if self.show_center_of_mass {
+                    if let Some((_, velocity)) =
+                        center_of_mass(&self.scene.particles, &self.config.custom_elements)
+                    {
+                        ui.label(format!(
+                            "Center of Mass Velocity: ({:.3}, {:.3})",
+                            velocity.x, velocity.y
+                        ));
+                    }
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Time Scale: ");
+                    ui.add(egui::Slider::new(&mut self.time_scale, 0..=20));
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.slow_motion.enabled, "Slow Motion on Impacts")
+                        .on_hover_text("Also triggered by every reaction");
+                    ui.add_enabled(
+                        self.slow_motion.enabled,
+                        egui::DragValue::new(&mut self.slow_motion.threshold)
+                            .speed(0.1)
+                            .clamp_range(0.0..=f32::MAX)
+                            .prefix("impulse ≥ "),
+                    );
+                    ui.add_enabled(
+                        self.slow_motion.enabled,
+                        egui::DragValue::new(&mut self.slow_motion.duration)
+                            .speed(0.1)
+                            .clamp_range(0.1..=10.0)
+                            .prefix("for ")
+                            .suffix("s"),
+                    );
+                });
+                ui.horizontal(|ui| match self.fast_forward {
+                    Some(ref fast_forward) => {
+                        let steps_per_second = fast_forward.steps_done as f32
+                            / fast_forward.start_time.elapsed().as_secs_f32();
+                        ui.add(
+                            egui::ProgressBar::new(
+                                fast_forward.steps_done as f32 / fast_forward.target_steps as f32,
+                            )
+                            .desired_width(150.0)
+                            .text(format!(
+                                "{}/{} ({:.0} steps/s)",
+                                fast_forward.steps_done,
+                                fast_forward.target_steps,
+                                steps_per_second,
+                            )),
+                        );
+                        if ui.button("Stop").clicked() {
+                            self.fast_forward = None;
+                        }
+                    }
+                    None => {
+                        if ui
+                            .button("Fast-forward")
+                            .on_hover_text("Steps as fast as possible using the fixed dt")
+                            .clicked()
+                        {
+                            self.fast_forward = Some(FastForward {
+                                target_steps: self.fast_forward_steps,
+                                steps_done: 0,
+                                start_time: std::time::Instant::now(),
+                            });
+                        }
+                        ui.add(
+                            egui::DragValue::new(&mut self.fast_forward_steps)
+                                .clamp_range(1..=usize::MAX)
+                                .suffix(" steps"),
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    const RECORDING_DIRECTORY: &str = "recording";
+                    let mut recording = self.recording.is_some();
+                    if ui
+                        .toggle_value(&mut recording, "Record")
+                        .on_hover_text("Saves every simulated frame as a PNG using the fixed dt")
+                        .changed()
+                    {
+                        if recording {
+                            match create_take_directory(RECORDING_DIRECTORY) {
+                                Ok(directory) => {
+                                    self.recording = Some(Recording {
+                                        directory,
+                                        frames: 0,
+                                    });
+                                    self.recording_status = None;
+                                }
+                                Err(error) => {
+                                    self.recording_status = Some(format!(
+                                        "Failed to create a directory in \
+                                        {RECORDING_DIRECTORY}: {error}"
+                                    ));
+                                }
+                            }
+                        } else if let Some(recording) = self.recording.take() {
+                            self.recording_status = Some(format!(
+                                "Saved {} frames to {}",
+                                recording.frames,
+                                recording.directory.display()
+                            ));
+                        }
+                    }
+                    ui.add_enabled_ui(self.recording.is_none(), |ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.recording_size[0])
+                                .clamp_range(16..=8192)
+                                .suffix("px"),
+                        );
+                        ui.label("x");
+                        ui.add(
+                            egui::DragValue::new(&mut self.recording_size[1])
+                                .clamp_range(16..=8192)
+                                .suffix("px"),
+                        );
+                    });
+                });
+                if let Some(ref recording) = self.recording {
+                    ui.label(format!(
+                        "Recording: {} frames in {}",
+                        recording.frames,
+                        recording.directory.display()
+                    ));
+                } else if let Some(ref status) = self.recording_status {
+                    ui.label(status);
+                }
+                ui.checkbox(
+                    &mut self.show_scene_while_fast_forwarding,
+                    "Show Scene While Fast-forwarding",
+                );
+
+                ui.horizontal(|ui| {
+                    ui.label("Preset: ");
+                    egui::ComboBox::from_id_source("Preset")
+                        .selected_text("Load...")
+                        .show_ui(ui, |ui| {
+                            for (name, preset) in presets::PRESETS {
+                                if ui.selectable_label(false, name).clicked() {
+                                    let mut scene = preset();
+                                    if let Some(std_dev) = self.preset_velocity_std_dev {
+                                        presets::randomize_velocities(
+                                            &mut scene,
+                                            &mut Rng::new(self.preset_velocity_seed),
+                                            std_dev,
+                                        );
+                                    }
+                                    replacement_scene = Some(scene);
+                                }
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    let mut randomize = self.preset_velocity_std_dev.is_some();
+                    if ui
+                        .checkbox(&mut randomize, "Random Velocities")
+                        .on_hover_text(
+                            "Loaded presets start with normally distributed velocities, \
+                             close to a gas in thermal equilibrium",
+                        )
+                        .changed()
+                    {
+                        self.preset_velocity_std_dev = randomize.then_some(3.0);
+                    }
+                    if let Some(std_dev) = &mut self.preset_velocity_std_dev {
+                        ui.label("σ:");
+                        ui.add(
+                            egui::DragValue::new(std_dev)
+                                .speed(0.05)
+                                .clamp_range(0.0..=f32::INFINITY),
+                        );
+                        ui.label("Seed:");
+                        ui.add(egui::DragValue::new(&mut self.preset_velocity_seed));
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    let clear_particles = ui.button("Clear Particles").clicked();
+                    let clear_all = ui.button("Clear All").clicked();
+                    if clear_particles || clear_all {
+                        let rectangles = if clear_all {
+                            vec![]
+                        } else {
+                            self.scene.rectangles.clone()
+                        };
+                        replacement_scene = Some(Scene {
+                            particles: vec![],
+                            bonds: BTreeMap::new(),
+                            rectangles,
+                        });
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    const SCENE_PATH: &str = "scene.txt";
+                    if ui.button("Save Scene").clicked() {
+                        let text =
+                            scene_file::save_scene(&self.scene, &self.config.custom_elements);
+                        self.scene_file_status = Some(match std::fs::write(SCENE_PATH, text) {
+                            Ok(()) => format!("Saved to {SCENE_PATH}"),
+                            Err(error) => format!("Failed to save {SCENE_PATH}: {error}"),
+                        });
+                    }
+                    if ui.button("Load Scene").clicked() {
+                        let scene = std::fs::read_to_string(SCENE_PATH)
+                            .map_err(|error| error.to_string())
+                            .and_then(|text| {
+                                scene_file::load_scene(&text, &mut self.config.custom_elements)
+                                    .map_err(|error| error.to_string())
+                            });
+                        self.scene_file_status = Some(match scene {
+                            Ok(scene) => {
+                                replacement_scene = Some(scene);
+                                format!("Loaded {SCENE_PATH}")
+                            }
+                            Err(error) => format!("Failed to load {SCENE_PATH}: {error}"),
+                        });
+                    }
+                    const SVG_PATH: &str = "scene.svg";
+                    if ui
+                        .button("Export SVG")
+                        .on_hover_text("Saves the current view at the recording size")
+                        .clicked()
+                    {
+                        let [width, height] = self.recording_size;
+                        let svg = svg::to_svg(
+                            &self.scene,
+                            &self.config.custom_elements,
+                            self.camera.position,
+                            self.camera.zoom,
+                            width,
+                            height,
+                        );
+                        self.scene_file_status = Some(match std::fs::write(SVG_PATH, svg) {
+                            Ok(()) => format!("Exported to {SVG_PATH}"),
+                            Err(error) => format!("Failed to export {SVG_PATH}: {error}"),
+                        });
+                    }
+                });
+                if let Some(ref status) = self.scene_file_status {
+                    ui.label(status);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.checkbox(&mut self.paused, "Paused").changed() && !self.paused {
+                        self.equilibrium.reset();
+                        self.paused_at_equilibrium = false;
+                    }
+                    self.step_requested |= ui
+                        .add_enabled(self.paused, egui::Button::new("Step"))
+                        .clicked();
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.pause_at_equilibrium, "Pause at Equilibrium")
+                        .on_hover_text(
+                            "Pauses once the kinetic energy stays below the threshold for a while",
+                        );
+                    ui.add_enabled(
+                        self.pause_at_equilibrium,
+                        egui::DragValue::new(&mut self.equilibrium.energy_threshold)
+                            .speed(0.001)
+                            .clamp_range(0.0..=f32::INFINITY)
+                            .prefix("energy < "),
+                    );
+                    ui.add_enabled(
+                        self.pause_at_equilibrium,
+                        egui::DragValue::new(&mut self.equilibrium.hold_steps)
+                            .clamp_range(1..=100000)
+                            .prefix("for ")
+                            .suffix(" steps"),
+                    );
+                });
+                if self.paused_at_equilibrium {
+                    ui.colored_label(
+                        egui::Color32::LIGHT_GREEN,
+                        "Paused automatically, the scene reached equilibrium",
+                    );
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.use_fixed_dt, "Fixed dt");
+                    ui.add_enabled(
+                        self.use_fixed_dt,
+                        egui::DragValue::new(&mut self.fixed_dt)
+                            .speed(0.0001)
+                            .clamp_range(0.0001..=0.1)
+                            .suffix("s"),
+                    );
+                });
+                ui.label(format!("Effective dt: {:.3}ms", 1000.0 * dt));
+
+                ui.horizontal(|ui| {
+                    ui.label(format!("Tunneled Particles: {}", self.tunneled_particles))
+                        .on_hover_text(
+                            "Particles that passed through a rectangle without colliding with it",
+                        );
+                    if ui.button("Reset").clicked() {
+                        self.tunneled_particles = 0;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(format!("Absorbed Particles: {}", self.absorbed_particles))
+                        .on_hover_text("Particles removed by absorber rectangles");
+                    if ui.button("Reset").clicked() {
+                        self.absorbed_particles = 0;
+                    }
+                });
+                let kinetic_energy: f32 = self
+                    .scene
+                    .particles
+                    .iter()
+                    .map(|particle| particle.kinetic_energy(&self.config.custom_elements))
+                    .sum();
+                ui.horizontal(|ui| {
+                    ui.label(format!("Reaction Energy: {:.3}", self.reaction_energy))
+                        .on_hover_text(
+                            "Kinetic energy released by reactions since the reset, negative \
+                             when they absorbed more than they released",
+                        );
+                    if ui.button("Reset").clicked() {
+                        self.reaction_energy = 0.0;
+                        self.initial_kinetic_energy = kinetic_energy;
+                    }
+                });
+                ui.label(format!(
+                    "Kinetic Energy: {kinetic_energy:.3} (reset at {:.3})",
+                    self.initial_kinetic_energy
+                ));
+                ui.label(format!(
+                    "Unaccounted Energy: {:.3}",
+                    kinetic_energy - self.initial_kinetic_energy - self.reaction_energy
+                ))
+                .on_hover_text(
+                    "Change in kinetic energy not explained by the reactions, which is only \
+                     expected to be 0 with no gravity, damping, bonds or other forces",
+                );
+
+                ui.collapsing("Replay", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Snapshot Interval: ");
+                        ui.add(
+                            egui::Slider::new(&mut self.replay.interval, 1..=120).suffix(" frames"),
+                        );
+                    });
+                    if self.replay.snapshots.is_empty() {
+                        ui.label("No snapshots recorded yet");
+                    } else {
+                        let last_snapshot = self.replay.snapshots.len() - 1;
+                        let response = ui.add_enabled(
+                            self.paused,
+                            egui::Slider::new(&mut self.replay.position, 0..=last_snapshot)
+                                .text("Timeline"),
+                        );
+                        if response.changed() {
+                            let scene = self.replay.snapshots[self.replay.position].clone();
+                            self.selected_object = self
+                                .selected_object
+                                .and_then(|object| object.find_in(&self.scene, &scene));
+                            self.followed_particle =
+                                self.followed_particle.and_then(
+                                    |i| match SelectedObject::Particle(i)
+                                        .find_in(&self.scene, &scene)
+                                    {
+                                        Some(SelectedObject::Particle(i)) => Some(i),
+                                        _ => None,
+                                    },
+                                );
+                            self.scene = scene;
+                            self.trails.clear();
+                            LatticeRelax::cancel(&mut self.lattice_relax, &mut self.config);
+                        }
+                        if !self.paused {
+                            ui.label("Pause to scrub the timeline");
+                        }
+                    }
+                });
+
+                ui.collapsing("Forces", |ui| {
+                    for (flag, name) in ForceFlags::NAMED {
+                        let mut enabled = self.config.forces.contains(flag);
+                        if ui.checkbox(&mut enabled, name).changed() {
+                            self.config.forces.set(flag, enabled);
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Gravity: ");
+                    if self.gravity_follows_screen {
+                        let mut strength = self.config.gravity.magnitude();
+                        ui.add(egui::DragValue::new(&mut strength).clamp_range(0.0..=f32::MAX));
+                        self.config.gravity = self.camera.screen_down() * strength;
+                    } else {
+                        ui.add(egui::DragValue::new(&mut self.config.gravity.x).prefix("x:"));
+                        ui.add(egui::DragValue::new(&mut self.config.gravity.y).prefix("y:"));
+                    }
+                    ui.checkbox(&mut self.gravity_follows_screen, "Down is Screen Down");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Magnetic Field: ");
+                    ui.add(egui::Slider::new(
+                        &mut self.config.magnetic_field,
+                        -10.0..=10.0,
+                    ));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Wall Skin: ");
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.wall_skin)
+                            .speed(0.001)
+                            .clamp_range(0.0..=1.0),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Wall Substeps: ");
+                    ui.add(egui::Slider::new(&mut self.config.wall_substeps, 1..=32))
+                        .on_hover_text(
+                            "Checks the walls this many times along each move, \
+                            so fast particles don't jump through thin walls",
+                        );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("N-body Gravity: ");
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.gravitational_constant)
+                            .speed(0.01)
+                            .prefix("G: "),
+                    )
+                    .on_hover_text("Every particle attracts every other, negative values repel");
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.gravity_softening)
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::INFINITY)
+                            .prefix("softening: "),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Contact Damping: ");
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.contact_damping)
+                            .speed(0.001)
+                            .clamp_range(0.0..=10.0),
+                    )
+                    .on_hover_text(
+                        "Fraction of the bounce lost per unit of impact speed. Unlike a \
+                         restitution coefficient, which scales every bounce the same, hard \
+                         impacts lose more energy than gentle contacts.",
+                    );
+                });
+                ui.horizontal(|ui| {
+                    let mut limited = self.config.max_collision_delta_v.is_some();
+                    if ui
+                        .checkbox(&mut limited, "Limit Collision Δv")
+                        .on_hover_text(
+                            "Caps how much particle collisions can change a velocity in one \
+                             step, so a bad contact in a dense cluster can't fling particles \
+                             away. Collisions stop conserving momentum when it's reached.",
+                        )
+                        .changed()
+                    {
+                        self.config.max_collision_delta_v = limited.then_some(100.0);
+                    }
+                    if let Some(max_delta_v) = &mut self.config.max_collision_delta_v {
+                        ui.add(
+                            egui::DragValue::new(max_delta_v)
+                                .speed(0.1)
+                                .clamp_range(0.0..=f32::INFINITY),
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Adhesion: ");
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.adhesion)
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::INFINITY),
+                    );
+                    ui.label("below");
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.adhesion_max_speed)
+                            .speed(0.01)
+                            .clamp_range(0.0..=f32::INFINITY)
+                            .suffix(" speed"),
+                    )
+                    .on_hover_text("Touching particles moving apart faster than this don't stick");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Cooling: ");
+                    if ui
+                        .add_enabled(
+                            self.anneal.is_none(),
+                            egui::Slider::new(&mut self.config.cooling_rate, -1.0..=2.0)
+                                .suffix(" /s"),
+                        )
+                        .on_hover_text(
+                            "Fraction of their velocity the particles lose per second, \
+                             negative heats them up",
+                        )
+                        .changed()
+                    {
+                        self.anneal = None;
+                    }
+                    if ui
+                        .button("Cool Once")
+                        .on_hover_text("Halves every velocity")
+                        .clicked()
+                    {
+                        for particle in &mut self.scene.particles {
+                            particle.velocity *= 0.5;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    match self.anneal {
+                        Some(ref anneal) => {
+                            ui.add(
+                                egui::ProgressBar::new((anneal.elapsed / anneal.duration).min(1.0))
+                                    .desired_width(120.0)
+                                    .text(format!("Annealing at {:.3} /s", anneal.cooling_rate())),
+                            );
+                            if ui.button("Stop").clicked() {
+                                self.anneal = None;
+                                self.config.cooling_rate = 0.0;
+                            }
+                        }
+                        None => {
+                            if ui
+                                .add_enabled(
+                                    self.config.cooling_rate > 0.0 && self.lattice_relax.is_none(),
+                                    egui::Button::new("Anneal"),
+                                )
+                                .on_hover_text(
+                                    "Ramps the cooling up to the rate above over the duration",
+                                )
+                                .clicked()
+                            {
+                                self.anneal = Some(Anneal {
+                                    rate: self.config.cooling_rate,
+                                    duration: self.anneal_duration,
+                                    elapsed: 0.0,
+                                });
+                                self.config.cooling_rate = 0.0;
+                            }
+                        }
+                    }
+                    ui.add(
+                        egui::DragValue::new(&mut self.anneal_duration)
+                            .speed(0.1)
+                            .clamp_range(0.1..=f32::INFINITY)
+                            .suffix(" s"),
+                    );
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Max Iterations: ");
+                    ui.add(egui::Slider::new(&mut self.config.max_iterations, 1..=500));
+                });
+                ui.label(format!(
+                    "Iterations Used: {}/{}",
+                    self.last_iterations, self.config.max_iterations
+                ));
+
+                ui.horizontal(|ui| {
+                    let mut automatic = self.config.cell_size.is_none();
+                    if ui
+                        .checkbox(&mut automatic, "Automatic Cell Size")
+                        .on_hover_text("Sizes the spatial hash cells to fit the largest particle")
+                        .changed()
+                    {
+                        self.config.cell_size = (!automatic).then_some(self.last_cell_size);
+                    }
+                    if let Some(cell_size) = &mut self.config.cell_size {
+                        ui.add(
+                            egui::DragValue::new(cell_size)
+                                .speed(0.01)
+                                .clamp_range(0.01..=f32::INFINITY),
+                        );
+                    }
+                });
+                if let Some(cell_size) = self.config.cell_size {
+                    if SpatialHash::cell_size_too_small(
+                        cell_size,
+                        &self.scene.particles,
+                        &self.config.custom_elements,
+                    ) {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(
+                                egui::Color32::YELLOW,
+                                "Cells are smaller than the largest particle, \
+                                 some collisions will be missed",
+                            );
+                            if ui.button("Fix").clicked() {
+                                self.config.cell_size = Some(SpatialHash::cell_size_for(
+                                    &self.scene.particles,
+                                    &self.config.custom_elements,
+                                ));
+                            }
+                        });
+                    }
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Contact Solver: ");
+                    egui::ComboBox::from_id_source("Contact Solver")
+                        .selected_text(self.config.contact_solver.name())
+                        .show_ui(ui, |ui| {
+                            for solver in ContactSolver::ALL {
+                                ui.selectable_value(
+                                    &mut self.config.contact_solver,
+                                    solver,
+                                    solver.name(),
+                                );
+                            }
+                        });
+                });
+                if self.config.contact_solver == ContactSolver::Islands {
+                    ui.horizontal(|ui| {
+                        ui.label("Resting Contact Speed: ");
+                        ui.add(
+                            egui::DragValue::new(&mut self.config.resting_contact_speed)
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::INFINITY),
+                        )
+                        .on_hover_text(
+                            "Islands whose contacts all approach slower than this \
+                            are resolved together",
+                        );
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Collision Response: ");
+                    egui::ComboBox::from_id_source("Collision Response")
+                        .selected_text(self.config.solver_model.name())
+                        .show_ui(ui, |ui| {
+                            for model in SolverModel::ALL {
+                                ui.selectable_value(
+                                    &mut self.config.solver_model,
+                                    model,
+                                    model.name(),
+                                );
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Product Direction: ");
+                    egui::ComboBox::from_id_source("Product Direction")
+                        .selected_text(self.config.product_direction.name())
+                        .show_ui(ui, |ui| {
+                            for direction in ProductDirection::ALL {
+                                ui.selectable_value(
+                                    &mut self.config.product_direction,
+                                    direction,
+                                    direction.name(),
+                                );
+                            }
+                        });
+                })
+                .response
+                .on_hover_text("Which way particles move after bonding");
+                ui.horizontal(|ui| {
+                    ui.label("Force Model: ");
+                    egui::ComboBox::from_id_source("Force Model")
+                        .selected_text(self.config.force_model.name())
+                        .show_ui(ui, |ui| {
+                            for force_model in ForceModel::ALL {
+                                ui.selectable_value(
+                                    &mut self.config.force_model,
+                                    force_model,
+                                    force_model.name(),
+                                );
+                            }
+                        });
+                });
+                if self.config.force_model == ForceModel::LennardJones {
+                    ui.horizontal(|ui| {
+                        ui.label("Cutoff (σ): ");
+                        ui.add(egui::Slider::new(
+                            &mut self.config.lennard_jones_cutoff,
+                            1.0..=5.0,
+                        ));
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Stage Order: ");
+                    egui::ComboBox::from_id_source("Stage Order")
+                        .selected_text(self.config.stage_order.name())
+                        .show_ui(ui, |ui| {
+                            for stage_order in StageOrder::ALL {
+                                ui.selectable_value(
+                                    &mut self.config.stage_order,
+                                    stage_order,
+                                    stage_order.name(),
+                                );
+                            }
+                        });
+                });
+
+                ui.collapsing("Reactions", |ui| {
+                    egui::Grid::new("Reactions Grid").show(ui, |ui| {
+                        ui.label("");
+                        for b in Element::ALL {
+                            ui.label(b.name());
+                        }
+                        ui.end_row();
+                        for a in Element::ALL {
+                            ui.label(a.name());
+                            for b in Element::ALL {
+                                let mut reacts = self.config.reactions[a][b];
+                                if ui.checkbox(&mut reacts, "").changed() {
+                                    self.config.reactions[a][b] = reacts;
+                                    self.config.reactions[b][a] = reacts;
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                    ui.label("Reaction Energy:");
+                    egui::Grid::new("Reaction Energy Grid").show(ui, |ui| {
+                        ui.label("");
+                        for b in Element::ALL {
+                            ui.label(b.name());
+                        }
+                        ui.end_row();
+                        for a in Element::ALL {
+                            ui.label(a.name());
+                            for b in Element::ALL {
+                                let mut energy = self.config.reaction_energy[a][b];
+                                if ui
+                                    .add(egui::DragValue::new(&mut energy).speed(0.1))
+                                    .on_hover_text("Positive values are exothermic")
+                                    .changed()
+                                {
+                                    self.config.reaction_energy[a][b] = energy;
+                                    self.config.reaction_energy[b][a] = energy;
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                    let mut form_water = !self.config.reaction_rules.is_empty();
+                    if ui
+                        .checkbox(&mut form_water, "Form Water (2H + O → H2O)")
+                        .on_hover_text("Bonded H2O molecules merge into a single particle")
+                        .changed()
+                    {
+                        self.config.reaction_rules = if form_water {
+                            let water = CustomElement::water();
+                            let custom_elements = &mut self.config.custom_elements;
+                            let index = match custom_elements
+                                .iter()
+                                .position(|custom_element| custom_element.name == water.name)
+                            {
+                                Some(index) => index,
+                                None => {
+                                    custom_elements.push(water);
+                                    custom_elements.len() - 1
+                                }
+                            };
+                            vec![ReactionRule::water(index)]
+                        } else {
+                            vec![]
+                        };
+                    }
+                });
+
+                ui.collapsing("Decay", |ui| {
+                    ui.label(
+                        "Particles whose lifetime runs out turn into the products of the \
+                        first rule for their element",
+                    );
+                    let mut removed = None;
+                    for (i, rule) in self.config.decay_rules.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_id_source(("Decaying Element", i))
+                                .selected_text(rule.element.name())
+                                .show_ui(ui, |ui| {
+                                    for element in Element::ALL {
+                                        ui.selectable_value(
+                                            &mut rule.element,
+                                            element,
+                                            element.name(),
+                                        );
+                                    }
+                                });
+                            ui.label("→");
+                            let mut removed_product = None;
+                            for (k, product) in rule.products.iter_mut().enumerate() {
+                                egui::ComboBox::from_id_source(("Decay Product", i, k))
+                                    .selected_text(product.symbol())
+                                    .show_ui(ui, |ui| {
+                                        for element in Element::ALL {
+                                            ui.selectable_value(product, element, element.name());
+                                        }
+                                        ui.separator();
+                                        if ui.button("Remove Product").clicked() {
+                                            removed_product = Some(k);
+                                        }
+                                    });
+                            }
+                            if let Some(k) = removed_product {
+                                rule.products.remove(k);
+                            }
+                            if rule.products.is_empty() {
+                                ui.label("nothing");
+                            }
+                            if ui.button("+").on_hover_text("Add a product").clicked() {
+                                rule.products.push(rule.element);
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = removed {
+                        self.config.decay_rules.remove(i);
+                    }
+                    if ui.button("Add Decay Rule").clicked() {
+                        self.config.decay_rules.push(DecayRule {
+                            element: Element::Hydrogen,
+                            products: vec![],
+                        });
+                    }
+                });
+
+                ui.allocate_space(ui.available_size());
+            });
+
+        if let Some(scene) = replacement_scene {
+            self.replace_scene(scene);
+        }
+        if stress_test_requested {
+            self.start_stress_test();
+        }
+        if restart_requested {
+            // The new process reads the settings as soon as it starts, before `on_exit` of this
+            // one would have written them
+            self.write_settings();
+            let scene_path = relaunch_scene_path();
+            let relaunched = std::fs::write(
+                &scene_path,
+                scene_file::save_scene(&self.scene, &self.config.custom_elements),
+            )
+            .and_then(|()| std::env::current_exe())
+            .and_then(|exe| {
+                std::process::Command::new(exe)
+                    .arg(format!(
+                        "--present-mode={}",
+                        present_mode_name(self.requested_present_mode)
+                    ))
+                    .arg(format!("--scene={}", scene_path.display()))
+                    .spawn()
+            });
+            match relaunched {
+                Ok(_) => frame.close(),
+                Err(error) => {
+                    self.scene_file_status =
+                        Some(format!("Failed to relaunch the simulation: {error}"));
+                }
+            }
+        }
+
+        egui::Window::new("Collision Log")
+            .open(&mut self.collision_log_window_open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.log_collisions, "Record");
+                    if ui.button("Clear").clicked() {
+                        self.collision_log.clear();
+                    }
+                    ui.label(format!("{} collisions", self.collision_log.len()));
+                });
+                ui.weak("Click a collision to select one of its particles");
+                ui.separator();
+                let row_height = ui.text_style_height(&egui::TextStyle::Body);
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .auto_shrink([false, true])
+                    .show_rows(ui, row_height, self.collision_log.len(), |ui, rows| {
+                        for logged in self.collision_log.range(rows) {
+                            let event = &logged.event;
+                            let find = |id| {
+                                self.scene
+                                    .particles
+                                    .iter()
+                                    .position(|particle| particle.id == id)
+                            };
+                            let particles = [find(event.ids.0), find(event.ids.1)];
+                            let name = |i: Option<usize>| match i {
+                                Some(i) => format!(
+                                    "#{i} {}",
+                                    self.scene.particles[i].symbol(&self.config.custom_elements)
+                                ),
+                                None => "(gone)".to_string(),
+                            };
+                            let text = format!(
+                                "{:.3}s  {} + {}  impulse {:.3} at ({:.2}, {:.2}){}",
+                                logged.time,
+                                name(particles[0]),
+                                name(particles[1]),
+                                event.impulse,
+                                event.position.x,
+                                event.position.y,
+                                if event.bonded { "  bonded" } else { "" },
+                            );
+                            let alive = particles.into_iter().flatten().next();
+                            if ui
+                                .add_enabled(
+                                    alive.is_some(),
+                                    egui::Label::new(text).sense(egui::Sense::click()),
+                                )
+                                .clicked()
+                            {
+                                self.selected_object = alive.map(SelectedObject::Particle);
+                            }
+                        }
+                    });
+            });
+
+        egui::Window::new("Speed Distribution")
+            .open(&mut self.speed_histogram_window_open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Element:");
+                    let element_name =
+                        |element: Option<Element>| element.map_or("All", |element| element.name());
+                    egui::ComboBox::from_id_source("Speed Histogram Element")
+                        .selected_text(element_name(self.speed_histogram_element))
+                        .show_ui(ui, |ui| {
+                            for element in
+                                std::iter::once(None).chain(Element::ALL.into_iter().map(Some))
+                            {
+                                ui.selectable_value(
+                                    &mut self.speed_histogram_element,
+                                    element,
+                                    element_name(element),
+                                );
+                            }
+                        });
+                    ui.label("Bins:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.speed_histogram_bins).clamp_range(1..=200),
+                    );
+                });
+                let live = self
+                    .scene
+                    .speed_histogram(self.speed_histogram_element, self.speed_histogram_bins);
+                ui.horizontal(|ui| {
+                    if self.frozen_speed_histogram.is_some() {
+                        if ui.button("Unfreeze").clicked() {
+                            self.frozen_speed_histogram = None;
+                        }
+                    } else if ui
+                        .button("Freeze")
+                        .on_hover_text("Keep showing the current distribution")
+                        .clicked()
+                    {
+                        self.frozen_speed_histogram = Some(live.clone());
+                    }
+                    if ui
+                        .button("Copy CSV")
+                        .on_hover_text("Copy the shown bins to the clipboard")
+                        .clicked()
+                    {
+                        let histogram = self.frozen_speed_histogram.as_ref().unwrap_or(&live);
+                        ui.output_mut(|output| output.copied_text = histogram.to_csv());
+                    }
+                });
+                let histogram = self.frozen_speed_histogram.as_ref().unwrap_or(&live);
+
+                let (rect, response) = ui.allocate_exact_size(
+                    egui::vec2(ui.available_width().max(200.0), 150.0),
+                    egui::Sense::hover(),
+                );
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+                let total: usize = histogram.counts.iter().sum();
+                if total == 0 {
+                    painter.text(
+                        rect.center(),
+                        egui::Align2::CENTER_CENTER,
+                        "No moving particles",
+                        egui::FontId::default(),
+                        egui::Color32::GRAY,
+                    );
+                    return;
+                }
+                let highest = histogram.counts.iter().copied().max().unwrap_or(1) as f32;
+                let bar_width = rect.width() / histogram.counts.len() as f32;
+                for (i, &count) in histogram.counts.iter().enumerate() {
+                    let left = rect.left() + i as f32 * bar_width;
+                    painter.rect_filled(
+                        egui::Rect::from_min_max(
+                            egui::pos2(
+                                left,
+                                rect.bottom() - rect.height() * count as f32 / highest,
+                            ),
+                            egui::pos2(left + bar_width - 1.0, rect.bottom()),
+                        ),
+                        0.0,
+                        egui::Color32::LIGHT_BLUE,
+                    );
+                }
+                if let Some(pointer) = response.hover_pos() {
+                    let bin = (((pointer.x - rect.left()) / bar_width) as usize)
+                        .min(histogram.counts.len() - 1);
+                    let width = histogram.bin_width();
+                    response.on_hover_text(format!(
+                        "{:.3} to {:.3}: {} particles",
+                        bin as f32 * width,
+                        (bin + 1) as f32 * width,
+                        histogram.counts[bin],
+                    ));
+                }
+                ui.horizontal(|ui| {
+                    ui.label("0");
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.label(format!("{:.3}", histogram.max_speed));
+                    });
+                });
+                ui.label(format!("{total} particles"));
+            });
+
+        egui::Window::new("Emitters")
+            .open(&mut self.emitters_window_open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Max Particles:");
+                    ui.add(egui::DragValue::new(&mut self.max_particles));
+                });
+                let mut removed = None;
+                for (i, emitter) in self.emitters.iter_mut().enumerate() {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("Position:");
+                        ui.add(egui::DragValue::new(&mut emitter.position.x).prefix("x:"));
+                        ui.add(egui::DragValue::new(&mut emitter.position.y).prefix("y:"));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Velocity:");
+                        ui.add(egui::DragValue::new(&mut emitter.velocity.x).prefix("x:"));
+                        ui.add(egui::DragValue::new(&mut emitter.velocity.y).prefix("y:"));
+                        ui.label("Spread:");
+                        ui.drag_angle(&mut emitter.spread);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Rate:");
+                        ui.add(
+                            egui::DragValue::new(&mut emitter.rate)
+                                .speed(0.1)
+                                .clamp_range(0.0..=1000.0)
+                                .suffix("/s"),
+                        );
+                        egui::ComboBox::from_id_source(("Emitter Element", i))
+                            .selected_text(emitter.element.name())
+                            .show_ui(ui, |ui| {
+                                for element in Element::ALL {
+                                    ui.selectable_value(
+                                        &mut emitter.element,
+                                        element,
+                                        element.name(),
+                                    );
+                                }
+                            });
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = removed {
+                    self.emitters.remove(i);
+                }
+                ui.separator();
+                if ui
+                    .button("Add Emitter")
+                    .on_hover_text("Click in the view to place the emitter")
+                    .clicked()
+                {
+                    self.placement = Some(Placement::Emitter);
+                }
+            });
 
-mod physics;
-mod rendering;
+        let mut relax_requested = false;
+        let mut relax_finished = false;
+        egui::Window::new("Lattice")
+            .open(&mut self.lattice_window_open)
+            .show(ctx, |ui| {
+                let lattice = &mut self.lattice;
+                ui.horizontal(|ui| {
+                    ui.label("Size:");
+                    ui.add(
+                        egui::DragValue::new(&mut lattice.rows)
+                            .clamp_range(1..=100)
+                            .suffix(" rows"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut lattice.cols)
+                            .clamp_range(1..=100)
+                            .suffix(" cols"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Spacing:");
+                    ui.add(
+                        egui::DragValue::new(&mut lattice.spacing)
+                            .speed(0.01)
+                            .clamp_range(0.01..=f32::INFINITY),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Element:");
+                    egui::ComboBox::from_id_source("Lattice Element")
+                        .selected_text(lattice.element.name())
+                        .show_ui(ui, |ui| {
+                            for element in Element::ALL {
+                                ui.selectable_value(&mut lattice.element, element, element.name());
+                            }
+                        });
+                });
+                ui.checkbox(&mut lattice.bond_neighbors, "Bond Neighbors");
+                if ui
+                    .button("Place")
+                    .on_hover_text("Click in the view to add the lattice centered there")
+                    .clicked()
+                {
+                    self.placement = Some(Placement::Lattice);
+                }
+                ui.separator();
+                ui.checkbox(
+                    &mut lattice.unfreeze_after_relax,
+                    "Unfreeze Anchors When Relaxed",
+                );
+                match self.lattice_relax {
+                    Some(ref relax) => {
+                        let kinetic_energy: f32 = self
+                            .scene
+                            .particles
+                            .iter()
+                            .filter(|particle| relax.particles.contains(&particle.id))
+                            .map(|particle| particle.kinetic_energy(&self.config.custom_elements))
+                            .sum();
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Relaxing, Kinetic Energy: {kinetic_energy:.5}"));
+                            if ui.button("Stop").clicked() {
+                                relax_finished = true;
+                            }
+                        });
+                    }
+                    None => {
+                        relax_requested = ui
+                            .add_enabled(
+                                self.anneal.is_none(),
+                                egui::Button::new("Build and Relax"),
+                            )
+                            .on_hover_text(
+                                "Adds the lattice with its neighbors bonded in the middle of \
+                                 the view, freezes its outer ring and simulates with extra \
+                                 cooling until the lattice stops moving, then pauses",
+                            )
+                            .on_disabled_hover_text("Annealing controls the cooling")
+                            .clicked();
+                    }
+                }
+            });
+        if relax_requested {
+            self.build_and_relax_lattice();
+        }
+        if relax_finished {
+            self.finish_lattice_relax();
+        }
 
-struct Camera {
-    position: Vector2<f32>,
-    zoom: f32,
-}
+        egui::Window::new("Elements")
+            .open(&mut self.elements_window_open)
+            .show(ctx, |ui| {
+                ui.label("Electronegativity:").on_hover_text(
+                    "The most electronegative reactant becomes the base element of a product",
+                );
+                ui.horizontal(|ui| {
+                    for element in Element::ALL {
+                        ui.add(
+                            egui::DragValue::new(&mut self.config.electronegativity[element])
+                                .speed(0.01)
+                                .clamp_range(0.0..=10.0)
+                                .prefix(format!("{}: ", element.symbol())),
+                        );
+                    }
+                });
+                for (i, custom_element) in self.config.custom_elements.iter_mut().enumerate() {
+                    let mut changed = false;
+                    ui.push_id(i, |ui| {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("Name:");
+                            changed |= ui.text_edit_singleline(&mut custom_element.name).changed();
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Base Element:");
+                            egui::ComboBox::from_id_source("Base Element")
+                                .selected_text(custom_element.base.name())
+                                .show_ui(ui, |ui| {
+                                    for element in Element::ALL {
+                                        changed |= ui
+                                            .selectable_value(
+                                                &mut custom_element.base,
+                                                element,
+                                                element.name(),
+                                            )
+                                            .changed();
+                                    }
+                                });
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Mass:");
+                            changed |= ui
+                                .add(
+                                    egui::DragValue::new(&mut custom_element.mass)
+                                        .speed(0.1)
+                                        .clamp_range(0.01..=f32::INFINITY),
+                                )
+                                .changed();
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Electrons to Share:");
+                            changed |= ui
+                                .add(egui::DragValue::new(&mut custom_element.electrons_to_share))
+                                .changed();
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Color:");
+                            changed |= egui::color_picker::color_edit_button_rgb(
+                                ui,
+                                custom_element.color.as_mut(),
+                            )
+                            .changed();
+                        });
+                    });
 
-enum SelectedObject {
-    Particle(usize),
-    Rectangle(usize),
-}
+                    if changed {
+                        for particle in &mut self.scene.particles {
+                            if particle.custom_element == Some(i) {
+                                particle.element = custom_element.base;
+                            }
+                        }
+                    }
+                }
 
-struct App {
-    last_frame_time: Option<std::time::Instant>,
-    info_window_open: bool,
-    selected_object: Option<SelectedObject>,
-    time_scale: usize,
-    camera: Camera,
-    particles: Vec<Particle>,
-    bonds: HashMap<(usize, usize), Bond>,
-    rectangles: Vec<Rectangle>,
-}
+                ui.separator();
+                if ui.button("Add Element").clicked() {
+                    self.config.custom_elements.push(CustomElement {
+                        name: format!("Custom {}", self.config.custom_elements.len()),
+                        base: Element::Hydrogen,
+                        mass: 1.0,
+                        electrons_to_share: 1,
+                        color: Vector3 {
+                            x: 0.0,
+                            y: 1.0,
+                            z: 0.0,
+                        },
+                    });
+                }
+            });
 
-impl App {
-    fn new(cc: &eframe::CreationContext) -> App {
-        create_render_state(cc);
-        App {
-            last_frame_time: None,
-            info_window_open: true,
-            selected_object: None,
-            time_scale: 1,
-            camera: Camera {
-                position: Vector2 { x: 0.0, y: 0.0 },
-                zoom: 0.25,
-            },
-            particles: vec![
-                Particle {
-                    position: Vector2 { x: 3.0, y: 0.0 },
-                    velocity: Vector2 { x: -1.0, y: 0.0 },
-                    element: Element::Oxygen,
-                },
-                Particle {
-                    position: Vector2 { x: -3.0, y: 0.0 },
-                    velocity: Vector2 { x: 0.1, y: 0.0 },
-                    element: Element::Hydrogen,
-                },
-                Particle {
-                    position: Vector2 { x: -6.0, y: 0.5 },
-                    velocity: Vector2 { x: 40.0, y: 10.0 },
-                    element: Element::Hydrogen,
-                },
-            ],
-            bonds: HashMap::from([]),
-            rectangles: vec![
-                Rectangle {
-                    position: Vector2 { x: -15.0, y: 0.0 },
-                    color: Vector3 {
-                        x: 0.1,
-                        y: 0.1,
-                        z: 0.1,
-                    },
-                    size: Vector2 { x: 1.0, y: 16.0 },
-                },
-                Rectangle {
-                    position: Vector2 { x: 15.0, y: 0.0 },
-                    color: Vector3 {
-                        x: 0.1,
-                        y: 0.1,
-                        z: 0.1,
-                    },
-                    size: Vector2 { x: 1.0, y: 16.0 },
-                },
-                Rectangle {
-                    position: Vector2 { x: 0.0, y: 7.5 },
-                    color: Vector3 {
-                        x: 0.1,
-                        y: 0.1,
-                        z: 0.1,
-                    },
-                    size: Vector2 { x: 30.0, y: 1.0 },
-                },
-                Rectangle {
-                    position: Vector2 { x: 0.0, y: -7.5 },
-                    color: Vector3 {
-                        x: 0.1,
-                        y: 0.1,
-                        z: 0.1,
-                    },
-                    size: Vector2 { x: 30.0, y: 1.0 },
-                },
-            ],
+        let mut measure_window_open = self.measure_region.is_some();
+        if let Some([start, end]) = self.measure_region {
+            egui::Window::new("Measure Region")
+                .open(&mut measure_window_open)
+                .show(ctx, |ui| {
+                    let min = Vector2::new(start.x.min(end.x), start.y.min(end.y));
+                    let max = Vector2::new(start.x.max(end.x), start.y.max(end.y));
+                    ui.label(format!(
+                        "From ({:.2}, {:.2}) to ({:.2}, {:.2})",
+                        min.x, min.y, max.x, max.y
+                    ));
+                    match region_stats(
+                        &self.scene.particles,
+                        &self.config.custom_elements,
+                        min,
+                        max,
+                    ) {
+                        Some(stats) => {
+                            ui.label(format!("Particles: {}", stats.count));
+                            ui.label(format!(
+                                "Average Velocity: ({:.3}, {:.3})",
+                                stats.velocity.x, stats.velocity.y
+                            ));
+                            ui.label(format!("Kinetic Energy: {:.3}", stats.kinetic_energy));
+                            ui.label(format!("Temperature: {:.3}", stats.temperature));
+                            for (element, &count) in &stats.composition {
+                                if count > 0 {
+                                    ui.label(format!("{}: {count}", element.name()));
+                                }
+                            }
+                        }
+                        None => {
+                            ui.weak("No particles in the region");
+                        }
+                    }
+                });
         }
-    }
-}
-
-impl eframe::App for App {
-    fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
-        let time = std::time::Instant::now();
-        let dt = time
-            .duration_since(self.last_frame_time.unwrap_or(time))
-            .as_secs_f32();
-        self.last_frame_time = Some(time);
-
-        for _ in 0..self.time_scale {
-            update_particles(
-                &mut self.particles,
-                &mut self.bonds,
-                &mut self.rectangles,
-                dt,
-            );
+        if !measure_window_open {
+            self.measure_region = None;
         }
 
-        egui::TopBottomPanel::top("Menu").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                self.info_window_open |= ui.button("Info").clicked();
+        if !self.non_finite_particles.is_empty() {
+            // Loading another scene or rewinding the replay fixes the problem too
+            self.non_finite_particles = self.scene.non_finite_particles();
+        }
+        if !self.non_finite_particles.is_empty() {
+            egui::Window::new("Simulation Error").show(ctx, |ui| {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!(
+                        "{} particles have a NaN or infinite position or velocity, \
+                        try a smaller dt or time scale",
+                        self.non_finite_particles.len()
+                    ),
+                );
+                if ui.button("Remove Particles").clicked() {
+                    for i in std::mem::take(&mut self.non_finite_particles)
+                        .into_iter()
+                        .rev()
+                    {
+                        self.remove_object(SelectedObject::Particle(i));
+                    }
+                }
             });
-        });
+        }
 
-        egui::Window::new("Info")
-            .open(&mut self.info_window_open)
+        let rectangle_count = self.scene.rectangles.len();
+        self.selected_rectangles.retain(|&i| i < rectangle_count);
+        let mut selected_rectangles_window_open = !self.selected_rectangles.is_empty();
+        egui::Window::new("Selected Rectangles")
+            .open(&mut selected_rectangles_window_open)
             .show(ctx, |ui| {
-                ui.label(format!("FPS: {:.3}", 1.0 / dt));
-                ui.label(format!("Frame Time: {:.3}ms", 1000.0 * dt));
-
-                // TODO: make this more accurate
-                // let mut energy = 0.0;
-                // for particle in &self.particles {
-                //     energy += 0.5 * particle.mass() * particle.velocity.magnitude2();
-                // }
-                // for bond in &self.bonds {
-                //     let distance = self.particles[bond.particle_a]
-                //         .position
-                //         .distance(self.particles[bond.particle_b].position)
-                //         - (self.particles[bond.particle_a].radius()
-                //             + self.particles[bond.particle_b].radius());
-                //     energy += 0.5 * bond.strength(&self.particles) * (distance * distance);
-                // }
-                // ui.label(format!("Energy: {:.3}", energy));
-
+                ui.label(format!(
+                    "{} rectangles selected, shift click to add or remove more",
+                    self.selected_rectangles.len()
+                ));
                 ui.horizontal(|ui| {
-                    ui.label("Time Scale: ");
-                    ui.add(egui::Slider::new(&mut self.time_scale, 0..=20));
+                    ui.label("Move:");
+                    let mut offset = Vector2::zero();
+                    ui.add(egui::DragValue::new(&mut offset.x).speed(0.1).prefix("x:"));
+                    ui.add(egui::DragValue::new(&mut offset.y).speed(0.1).prefix("y:"));
+                    for &i in &self.selected_rectangles {
+                        self.scene.rectangles[i].position += offset;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Color:");
+                    let first_color = self.scene.rectangles[self.selected_rectangles[0]].color;
+                    let mut color = first_color;
+                    if egui::color_picker::color_edit_button_rgb(ui, color.as_mut()).changed() {
+                        for &i in &self.selected_rectangles {
+                            self.scene.rectangles[i].color = color;
+                        }
+                    }
+                    if self
+                        .selected_rectangles
+                        .iter()
+                        .any(|&i| self.scene.rectangles[i].color != first_color)
+                    {
+                        ui.weak("(mixed)");
+                    }
                 });
-
-                ui.allocate_space(ui.available_size());
             });
+        if !selected_rectangles_window_open {
+            self.selected_rectangles.clear();
+        }
 
         let mut selected_object_window_open = self.selected_object.is_some();
         egui::Window::new("Selected Object")
             .open(&mut selected_object_window_open)
             .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Focus")
+                        .on_hover_text("Center the camera on the object (C)")
+                        .clicked()
+                    {
+                        self.focus_selected();
+                    }
+                    if let Some(SelectedObject::Particle(i)) = self.selected_object {
+                        let mut following = self.followed_particle == Some(i);
+                        if ui
+                            .checkbox(&mut following, "Follow")
+                            .on_hover_text("Keep the camera centered on the particle")
+                            .changed()
+                        {
+                            self.followed_particle = following.then_some(i);
+                        }
+                    }
+                });
                 match self.selected_object {
                     Some(SelectedObject::Particle(i)) => {
                         ui.label("Particle:");
                         ui.horizontal(|ui| {
                             ui.label("Position:");
                             ui.add(
-                                egui::DragValue::new(&mut self.particles[i].position.x)
+                                egui::DragValue::new(&mut self.scene.particles[i].position.x)
                                     .prefix("x:"),
                             );
                             ui.add(
-                                egui::DragValue::new(&mut self.particles[i].position.y)
+                                egui::DragValue::new(&mut self.scene.particles[i].position.y)
                                     .prefix("y:"),
                             );
                         });
                         ui.horizontal(|ui| {
                             ui.label("Velocity:");
                             ui.add(
-                                egui::DragValue::new(&mut self.particles[i].velocity.x)
+                                egui::DragValue::new(&mut self.scene.particles[i].velocity.x)
                                     .prefix("x:"),
                             );
                             ui.add(
-                                egui::DragValue::new(&mut self.particles[i].velocity.y)
+                                egui::DragValue::new(&mut self.scene.particles[i].velocity.y)
                                     .prefix("y:"),
                             );
                         });
+                        ui.horizontal(|ui| {
+                            ui.label("Charge:");
+                            ui.add(
+                                egui::DragValue::new(&mut self.scene.particles[i].charge)
+                                    .speed(0.1),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            let particle = &mut self.scene.particles[i];
+                            ui.label("Layer:");
+                            ui.add(egui::DragValue::new(&mut particle.layer).clamp_range(0..=31));
+                            ui.label("Collides With:");
+                            ui.add(
+                                egui::DragValue::new(&mut particle.collision_mask)
+                                    .hexadecimal(8, false, true),
+                            )
+                            .on_hover_text("Bit n is set to collide with particles on layer n");
+                            if ui.button("Own Layer").clicked() {
+                                particle.collision_mask = 1 << particle.layer;
+                            }
+                            if ui.button("All").clicked() {
+                                particle.collision_mask = u32::MAX;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            let particle = &mut self.scene.particles[i];
+                            let mut decays = particle.lifetime.is_some();
+                            if ui.checkbox(&mut decays, "Lifetime").changed() {
+                                particle.lifetime = decays.then_some(1.0);
+                            }
+                            if let Some(ref mut lifetime) = particle.lifetime {
+                                ui.add(
+                                    egui::DragValue::new(lifetime)
+                                        .speed(0.1)
+                                        .clamp_range(0.0..=f32::INFINITY)
+                                        .suffix("s"),
+                                );
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Frozen:");
+                            let particle = &mut self.scene.particles[i];
+                            if ui.checkbox(&mut particle.frozen, "").changed() {
+                                particle.velocity = Vector2::zero();
+                            }
+                        });
                         ui.horizontal(|ui| {
                             ui.label("Radius:");
-                            ui.add(egui::DragValue::new(&mut self.particles[i].radius()));
+                            let particle = &mut self.scene.particles[i];
+                            let mut radius = particle.radius(&self.config.custom_elements);
+                            if ui
+                                .add(
+                                    egui::DragValue::new(&mut radius)
+                                        .speed(0.01)
+                                        .clamp_range(0.01..=f32::INFINITY),
+                                )
+                                .changed()
+                            {
+                                particle.radius_override = Some(radius);
+                            }
+                            if particle.radius_override.is_some() {
+                                if ui.button("Reset").clicked() {
+                                    particle.radius_override = None;
+                                }
+                            } else {
+                                ui.weak("(derived from mass)");
+                            }
                         });
                         ui.horizontal(|ui| {
-                            ui.label("Mass:");
-                            ui.add(egui::DragValue::new(&mut self.particles[i].mass()));
+                            let particle = &mut self.scene.particles[i];
+                            let mut is_ellipse = particle.ellipse.is_some();
+                            if ui.checkbox(&mut is_ellipse, "Ellipse").changed() {
+                                particle.ellipse = is_ellipse.then(|| Ellipse {
+                                    semi_axes: particle.semi_axes(&self.config.custom_elements),
+                                    orientation: 0.0,
+                                });
+                            }
+                            if let Some(ref mut ellipse) = particle.ellipse {
+                                ui.add(
+                                    egui::DragValue::new(&mut ellipse.semi_axes.x)
+                                        .speed(0.01)
+                                        .clamp_range(0.01..=f32::INFINITY),
+                                );
+                                ui.add(
+                                    egui::DragValue::new(&mut ellipse.semi_axes.y)
+                                        .speed(0.01)
+                                        .clamp_range(0.01..=f32::INFINITY),
+                                );
+                                ui.drag_angle(&mut ellipse.orientation);
+                            }
                         });
                         ui.add_enabled_ui(false, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Mass:");
+                                ui.add(egui::DragValue::new(
+                                    &mut self.scene.particles[i].mass(&self.config.custom_elements),
+                                ))
+                                .on_disabled_hover_text("Derived from the element");
+                            });
                             ui.horizontal(|ui| {
                                 ui.label("Kinetic Energy:");
                                 ui.add(egui::DragValue::new(
-                                    &mut (0.5
-                                        * self.particles[i].mass()
-                                        * self.particles[i].velocity.magnitude2()),
+                                    &mut self.scene.particles[i]
+                                        .kinetic_energy(&self.config.custom_elements),
                                 ));
                             });
                         });
-                        ui.label(format!(
-                            "Element: {}",
-                            match self.particles[i].element {
-                                Element::Hydrogen => "Hydrogen",
-                                Element::Oxygen => "Oxygen",
-                            },
-                        ));
+                        ui.horizontal(|ui| {
+                            ui.label("Element:");
+                            let particle = &mut self.scene.particles[i];
+                            egui::ComboBox::from_id_source("Element")
+                                .selected_text(
+                                    particle
+                                        .element_name(&self.config.custom_elements)
+                                        .to_string(),
+                                )
+                                .show_ui(ui, |ui| {
+                                    for element in Element::ALL {
+                                        if ui
+                                            .selectable_label(
+                                                particle.custom_element.is_none()
+                                                    && particle.element == element,
+                                                element.name(),
+                                            )
+                                            .clicked()
+                                        {
+                                            particle.element = element;
+                                            particle.custom_element = None;
+                                        }
+                                    }
+                                    for (index, custom_element) in
+                                        self.config.custom_elements.iter().enumerate()
+                                    {
+                                        if ui
+                                            .selectable_label(
+                                                particle.custom_element == Some(index),
+                                                &custom_element.name,
+                                            )
+                                            .clicked()
+                                        {
+                                            particle.element = custom_element.base;
+                                            particle.custom_element = Some(index);
+                                        }
+                                    }
+                                });
+                        });
+                        let capacity = self.scene.particles[i]
+                            .max_electrons_to_share(&self.config.custom_elements);
+                        let bonded = bond_count(i, &self.scene.bonds);
+                        match electrons_to_share(
+                            i,
+                            &self.scene.particles,
+                            &self.scene.bonds,
+                            &self.config.custom_elements,
+                        ) {
+                            Some(electrons) => {
+                                ui.label(format!(
+                                    "Electrons to Share: {electrons} ({capacity} capacity, \
+                                    {bonded} bonded)"
+                                ));
+                            }
+                            None => {
+                                if self.over_attached_warned.insert(self.scene.particles[i].id) {
+                                    eprintln!(
+                                        "WARNING: Particle {i} has {bonded} bonds but can only \
+                                        share {capacity} electrons"
+                                    );
+                                }
+                                ui.colored_label(
+                                    egui::Color32::RED,
+                                    format!(
+                                        "Electrons to Share: 0 ({capacity} capacity, {bonded} \
+                                        bonded, over-attached)"
+                                    ),
+                                );
+                            }
+                        }
                     }
                     Some(SelectedObject::Rectangle(i)) => {
                         ui.label("Rectangle:");
                         ui.horizontal(|ui| {
                             ui.label("Position:");
                             ui.add(
-                                egui::DragValue::new(&mut self.rectangles[i].position.x)
+                                egui::DragValue::new(&mut self.scene.rectangles[i].position.x)
                                     .prefix("x:"),
                             );
                             ui.add(
-                                egui::DragValue::new(&mut self.rectangles[i].position.y)
+                                egui::DragValue::new(&mut self.scene.rectangles[i].position.y)
                                     .prefix("y:"),
                             );
                         });
@@ -230,20 +3865,42 @@ impl eframe::App for App {
                             ui.label("Color:");
                             egui::color_picker::color_edit_button_rgb(
                                 ui,
-                                self.rectangles[i].color.as_mut(),
+                                self.scene.rectangles[i].color.as_mut(),
                             );
                         });
                         ui.horizontal(|ui| {
                             ui.label("Size:");
                             ui.add(
-                                egui::DragValue::new(&mut self.rectangles[i].size.x)
+                                egui::DragValue::new(&mut self.scene.rectangles[i].size.x)
                                     .prefix("width:"),
                             );
                             ui.add(
-                                egui::DragValue::new(&mut self.rectangles[i].size.y)
+                                egui::DragValue::new(&mut self.scene.rectangles[i].size.y)
                                     .prefix("height:"),
                             );
                         });
+                        ui.checkbox(&mut self.scene.rectangles[i].absorber, "Absorber")
+                            .on_hover_text("Removes the particles that enter it");
+                        ui.horizontal(|ui| {
+                            ui.label("Material:");
+                            let rectangle = &mut self.scene.rectangles[i];
+                            egui::ComboBox::from_id_source("Wall Material")
+                                .selected_text(rectangle.material.name())
+                                .show_ui(ui, |ui| {
+                                    for material in WallMaterial::ALL {
+                                        if ui
+                                            .selectable_value(
+                                                &mut rectangle.material,
+                                                material,
+                                                material.name(),
+                                            )
+                                            .clicked()
+                                        {
+                                            rectangle.color = material.color();
+                                        }
+                                    }
+                                });
+                        });
                     }
                     None => unreachable!(),
                 }
@@ -256,84 +3913,586 @@ impl eframe::App for App {
         egui::CentralPanel::default()
             .frame(egui::Frame::none().fill(egui::Color32::from_rgb(0, 0, 0)))
             .show(ctx, |ui| {
-                let (rect, response) =
+                let (rect, mut response) =
                     ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+                self.click_filter.update(ctx, &mut response);
+                if walls_requested {
+                    self.scene.rectangles.extend(presets::walls_inside(
+                        self.camera.screen_to_world(rect, rect.left_bottom()),
+                        self.camera.screen_to_world(rect, rect.right_top()),
+                    ));
+                }
                 let aspect = rect.width() / rect.height();
+                let minimap_rect = (self.show_minimap && self.show_overlays).then(|| {
+                    egui::Rect::from_min_size(
+                        rect.right_bottom() - egui::vec2(210.0, 160.0),
+                        egui::vec2(200.0, 150.0),
+                    )
+                });
 
-                if response.dragged_by(egui::PointerButton::Secondary) {
+                if self.click_filter.dragged_by(egui::PointerButton::Secondary) {
                     let delta = response.drag_delta();
-                    self.camera.position.x -=
-                        delta.x / self.camera.zoom / rect.width() * 2.0 * aspect;
-                    self.camera.position.y += delta.y / self.camera.zoom / rect.height() * 2.0;
-                }
-
-                if response.clicked_by(egui::PointerButton::Primary) {
-                    let mouse_position = ((response.interact_pointer_pos().unwrap()
-                        - rect.left_top())
-                        / rect.size()
-                        * 2.0
-                        - egui::vec2(1.0, 1.0))
-                        * egui::vec2(1.0, -1.0);
-                    let world_position = Vector2 {
-                        x: mouse_position.x * aspect / self.camera.zoom + self.camera.position.x,
-                        y: mouse_position.y / self.camera.zoom + self.camera.position.y,
+                    let world_delta = Vector2 {
+                        x: -delta.x / self.camera.zoom / rect.width() * 2.0 * aspect,
+                        y: delta.y / self.camera.zoom / rect.height() * 2.0,
                     };
-                    self.selected_object = 'search: {
-                        for (i, particle) in self.particles.iter().enumerate() {
-                            if (world_position - particle.position).magnitude2()
-                                <= particle.radius() * particle.radius()
-                            {
-                                break 'search Some(SelectedObject::Particle(i));
+                    self.camera.position += world_delta;
+                    if self.camera_inertia && camera_dt > 0.0 {
+                        self.camera.velocity = world_delta / camera_dt;
+                    }
+                } else if self.camera_inertia {
+                    self.camera.apply_inertia(camera_dt);
+                }
+
+                if self.tool == Tool::Measure {
+                    if let Some(position) = response.interact_pointer_pos() {
+                        let world_position = self.camera.screen_to_world(rect, position);
+                        if let Some(origin) = self
+                            .click_filter
+                            .drag_started_by(egui::PointerButton::Primary)
+                        {
+                            let start = self.camera.screen_to_world(rect, origin);
+                            self.measure_region = Some([start, world_position]);
+                        } else if self.click_filter.dragged_by(egui::PointerButton::Primary) {
+                            if let Some([_, ref mut end]) = self.measure_region {
+                                *end = world_position;
                             }
                         }
-                        for (i, rectangle) in self.rectangles.iter().enumerate() {
-                            let relative_position = world_position - rectangle.position;
-                            if relative_position.x.abs() <= rectangle.size.x * 0.5
-                                && relative_position.y.abs() <= rectangle.size.y * 0.5
-                            {
-                                break 'search Some(SelectedObject::Rectangle(i));
+                    }
+                }
+
+                if response.clicked_by(egui::PointerButton::Primary)
+                    && !minimap_rect.is_some_and(|minimap_rect| {
+                        minimap_rect.contains(response.interact_pointer_pos().unwrap())
+                    })
+                {
+                    let world_position = self
+                        .camera
+                        .screen_to_world(rect, response.interact_pointer_pos().unwrap());
+                    let object = SelectedObject::at(
+                        &self.scene,
+                        &self.config.custom_elements,
+                        world_position,
+                    );
+                    if let Some(placement) = self.placement {
+                        self.place(placement, world_position);
+                        if !ctx.input(|input| input.modifiers.shift) {
+                            self.placement = None;
+                        }
+                    } else {
+                        match self.tool {
+                            Tool::Select if ctx.input(|input| input.modifiers.shift) => {
+                                if let Some(SelectedObject::Rectangle(i)) =
+                                    self.selected_object.take()
+                                {
+                                    if !self.selected_rectangles.contains(&i) {
+                                        self.selected_rectangles.push(i);
+                                    }
+                                }
+                                if let Some(SelectedObject::Rectangle(i)) = object {
+                                    if let Some(position) =
+                                        self.selected_rectangles.iter().position(|&j| j == i)
+                                    {
+                                        self.selected_rectangles.remove(position);
+                                    } else {
+                                        self.selected_rectangles.push(i);
+                                    }
+                                }
+                            }
+                            Tool::Select => {
+                                self.selected_object = object;
+                                self.selected_rectangles.clear();
+                            }
+                            Tool::Bond => match (self.bond_start, object) {
+                                (None, Some(SelectedObject::Particle(i))) => {
+                                    self.bond_start = Some(i)
+                                }
+                                (Some(a), Some(SelectedObject::Particle(b))) if a != b => {
+                                    let key = (a.min(b), a.max(b));
+                                    if self.scene.bonds.remove(&key).is_none() {
+                                        let rest_length = self.scene.particles[a]
+                                            .position
+                                            .distance(self.scene.particles[b].position);
+                                        self.scene.bonds.insert(key, Bond { rest_length });
+                                    }
+                                    self.bond_start = None;
+                                }
+                                _ => self.bond_start = None,
+                            },
+                            Tool::Measure => {}
+                            Tool::Impulse => {
+                                apply_impulse(
+                                    &mut self.scene.particles,
+                                    &self.config.custom_elements,
+                                    world_position,
+                                    self.impulse_radius,
+                                    self.impulse_strength,
+                                );
+                                self.impulse_ring =
+                                    Some((world_position, std::time::Instant::now()));
                             }
                         }
-                        None
-                    };
+                    }
+                }
+                if self.tool != Tool::Bond {
+                    self.bond_start = None;
+                }
+
+                if response.secondary_clicked() {
+                    self.context_object = response.interact_pointer_pos().and_then(|position| {
+                        SelectedObject::at(
+                            &self.scene,
+                            &self.config.custom_elements,
+                            self.camera.screen_to_world(rect, position),
+                        )
+                    });
+                }
+                if let Some(object) = self
+                    .context_object
+                    .filter(|object| object.exists_in(&self.scene))
+                {
+                    response
+                        .clone()
+                        .context_menu(|ui| self.show_context_menu(ui, object));
                 }
 
+                // Zoom with the scroll wheel while hovering the viewport, or with +/- from anywhere
+                // as long as no text field or drag value has keyboard focus
                 if response.hovered() {
-                    ctx.input(|input| match input.scroll_delta.y.total_cmp(&0.0) {
-                        std::cmp::Ordering::Less => self.camera.zoom *= 0.9,
-                        std::cmp::Ordering::Greater => self.camera.zoom /= 0.9,
-                        _ => {}
+                    // egui scrolls this many points for every notch of a mouse wheel
+                    const POINTS_PER_NOTCH: f32 = 50.0;
+                    let notches = ctx.input(|input| input.scroll_delta.y) / POINTS_PER_NOTCH;
+                    if notches != 0.0 {
+                        self.camera.zoom_input(
+                            Camera::ZOOM_STEP.powf(-notches * self.zoom_sensitivity),
+                            self.camera_inertia,
+                        );
+                    }
+                }
+                if ctx.memory(|memory| memory.focus().is_none()) {
+                    let step = Camera::ZOOM_STEP.powf(self.zoom_sensitivity);
+                    ctx.input(|input| {
+                        if input.key_pressed(egui::Key::PlusEquals) {
+                            self.camera.zoom_input(1.0 / step, self.camera_inertia);
+                        }
+                        if input.key_pressed(egui::Key::Minus) {
+                            self.camera.zoom_input(step, self.camera_inertia);
+                        }
                     });
                 }
 
-                ui.painter().add(Callback::new_paint_callback(
-                    rect,
-                    RenderCallback {
-                        camera: GpuCamera {
-                            position: self.camera.position,
-                            aspect,
-                            zoom: self.camera.zoom,
-                        },
-                        circles: self
-                            .particles
-                            .iter()
-                            .map(|particle| GpuCircle {
-                                position: particle.position,
-                                color: particle.color(),
-                                radius: particle.radius(),
-                            })
-                            .collect(),
-                        rectangles: self
-                            .rectangles
-                            .iter()
-                            .map(|rectangle| GpuRectangle {
-                                position: rectangle.position,
-                                color: rectangle.color,
-                                size: rectangle.size,
-                            })
-                            .collect(),
-                    },
-                ));
+                let hovered_particle = response
+                    .hover_pos()
+                    .and_then(|position| {
+                        SelectedObject::at(
+                            &self.scene,
+                            &self.config.custom_elements,
+                            self.camera.screen_to_world(rect, position),
+                        )
+                    })
+                    .and_then(|object| match object {
+                        SelectedObject::Particle(i) => Some(i),
+                        SelectedObject::Rectangle(_) => None,
+                    });
+
+                self.followed_particle = self
+                    .followed_particle
+                    .filter(|&i| i < self.scene.particles.len());
+                if let Some(i) = self.followed_particle {
+                    let target = self.scene.particles[i].position;
+                    self.camera.position = if self.follow_smoothing > 0.0 {
+                        // Independent of the frame rate, unlike a fixed lerp per frame
+                        let t = 1.0 - (-camera_dt / self.follow_smoothing).exp();
+                        self.camera.position.lerp(target, t)
+                    } else {
+                        target
+                    };
+                    self.camera.velocity = Vector2::zero();
+                }
+
+                self.sync_selection();
+                let ghost =
+                    self.placement
+                        .zip(response.hover_pos())
+                        .map(|(placement, position)| {
+                            self.placed_scene(
+                                placement,
+                                self.camera.screen_to_world(rect, position),
+                            )
+                        });
+                if self.fast_forward.is_none() || self.show_scene_while_fast_forwarding {
+                    let mut callback = self.render_callback(aspect, hovered_particle);
+                    if let Some(ref ghost) = ghost {
+                        // Drawn last so the ghost blends over the scene
+                        callback.circles.extend(
+                            ghost.particles.iter().map(|particle| {
+                                self.gpu_circle(particle, GpuCircle::NO_OUTLINE, 0.4)
+                            }),
+                        );
+                    }
+                    ui.painter()
+                        .add(Callback::new_paint_callback(rect, callback));
+                }
+
+                // Clipping everything away hides the overlays without checking before each one
+                let painter = if self.show_overlays {
+                    ui.painter_at(rect)
+                } else {
+                    ui.painter_at(egui::Rect::NOTHING)
+                };
+                if let Some(ref ghost) = ghost {
+                    for &(a, b) in ghost.bonds.keys() {
+                        painter.line_segment(
+                            [
+                                self.camera
+                                    .world_to_screen(rect, ghost.particles[a].position),
+                                self.camera
+                                    .world_to_screen(rect, ghost.particles[b].position),
+                            ],
+                            egui::Stroke::new(2.0, egui::Color32::from_white_alpha(60)),
+                        );
+                    }
+                }
+                if let Some([start, end]) = self.measure_region {
+                    painter.rect(
+                        egui::Rect::from_two_pos(
+                            self.camera.world_to_screen(rect, start),
+                            self.camera.world_to_screen(rect, end),
+                        ),
+                        0.0,
+                        egui::Color32::from_rgba_unmultiplied(100, 180, 255, 30),
+                        egui::Stroke::new(1.5, egui::Color32::LIGHT_BLUE),
+                    );
+                }
+                for emitter in &self.emitters {
+                    let position = self.camera.world_to_screen(rect, emitter.position);
+                    let direction = emitter.direction(20.0);
+                    let color = egui::Color32::from_rgb(255, 128, 255);
+                    painter.rect_stroke(
+                        egui::Rect::from_center_size(position, egui::vec2(10.0, 10.0)),
+                        0.0,
+                        egui::Stroke::new(2.0, color),
+                    );
+                    painter.arrow(
+                        position,
+                        egui::vec2(direction.x, -direction.y),
+                        egui::Stroke::new(2.0, color),
+                    );
+                }
+                if self.show_spatial_hash && self.last_cell_size > 0.0 {
+                    let spatial_hash = SpatialHash::new(&self.scene.particles, self.last_cell_size);
+                    let cell_size = Vector2::new(1.0, 1.0) * spatial_hash.cell_size;
+                    let most_particles = spatial_hash.cells.values().map(Vec::len).max();
+                    for (&(x, y), particles) in &spatial_hash.cells {
+                        let corner = Vector2::new(x as f32, y as f32) * spatial_hash.cell_size;
+                        let occupancy = particles.len() as f32 / most_particles.unwrap() as f32;
+                        painter.rect(
+                            egui::Rect::from_two_pos(
+                                self.camera.world_to_screen(rect, corner),
+                                self.camera.world_to_screen(rect, corner + cell_size),
+                            ),
+                            0.0,
+                            egui::Color32::from_rgba_unmultiplied(
+                                0,
+                                255,
+                                0,
+                                (occupancy * 96.0) as u8,
+                            ),
+                            egui::Stroke::new(1.0, egui::Color32::DARK_GREEN),
+                        );
+                    }
+                }
+                for rectangle in self
+                    .scene
+                    .rectangles
+                    .iter()
+                    .filter(|rectangle| rectangle.absorber)
+                {
+                    painter.rect_stroke(
+                        egui::Rect::from_two_pos(
+                            self.camera
+                                .world_to_screen(rect, rectangle.position - rectangle.size * 0.5),
+                            self.camera
+                                .world_to_screen(rect, rectangle.position + rectangle.size * 0.5),
+                        ),
+                        0.0,
+                        egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 80, 80)),
+                    );
+                }
+                for rectangle in self
+                    .scene
+                    .rectangles
+                    .iter()
+                    .filter(|rectangle| rectangle.selected)
+                {
+                    painter.rect_stroke(
+                        egui::Rect::from_two_pos(
+                            self.camera
+                                .world_to_screen(rect, rectangle.position - rectangle.size * 0.5),
+                            self.camera
+                                .world_to_screen(rect, rectangle.position + rectangle.size * 0.5),
+                        ),
+                        0.0,
+                        egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 204, 51)),
+                    );
+                }
+                if self.show_speed_trails {
+                    for trail in &self.trails.points {
+                        // Older segments fade out towards the end of the tail
+                        for (age, (&(start, _), &(end, speed))) in
+                            trail.iter().zip(trail.iter().skip(1)).rev().enumerate()
+                        {
+                            let fade = 1.0 - age as f32 / trail.len() as f32;
+                            painter.line_segment(
+                                [
+                                    self.camera.world_to_screen(rect, start),
+                                    self.camera.world_to_screen(rect, end),
+                                ],
+                                egui::Stroke::new(
+                                    1.5,
+                                    speed_color(speed / self.trails.max_speed).gamma_multiply(fade),
+                                ),
+                            );
+                        }
+                    }
+                }
+                let bond_painter = ui.painter_at(rect);
+                for (&(a, b), bond) in self.scene.bonds.iter().filter(|_| self.show_bonds) {
+                    let color = if self.show_bond_stress {
+                        let length = self.scene.particles[a]
+                            .position
+                            .distance(self.scene.particles[b].position);
+                        let stress = ((length - bond.rest_length)
+                            / bond.rest_length
+                            / self.bond_stress_scale)
+                            .clamp(-1.0, 1.0);
+                        // Blue when compressed, white at rest and red when stretched
+                        let other = (255.0 * (1.0 - stress.abs())) as u8;
+                        if stress < 0.0 {
+                            egui::Color32::from_rgb(other, other, 255)
+                        } else {
+                            egui::Color32::from_rgb(255, other, other)
+                        }
+                    } else {
+                        egui::Color32::GRAY
+                    };
+                    // In pixels so the bonds stay visible however far the view is zoomed out
+                    let width = if self.show_bond_strength {
+                        (Bond::strength(&self.scene.particles[a], &self.scene.particles[b])
+                            * self.bond_thickness_scale)
+                            .clamp(1.0, 10.0)
+                    } else {
+                        2.0
+                    };
+                    bond_painter.line_segment(
+                        [
+                            self.camera
+                                .world_to_screen(rect, self.scene.particles[a].position),
+                            self.camera
+                                .world_to_screen(rect, self.scene.particles[b].position),
+                        ],
+                        egui::Stroke::new(width, color),
+                    );
+                }
+                if let Some(i) = self.bond_start {
+                    let particle = &self.scene.particles[i];
+                    painter.circle_stroke(
+                        self.camera.world_to_screen(rect, particle.position),
+                        particle.radius(&self.config.custom_elements)
+                            * self.camera.zoom
+                            * rect.height()
+                            * 0.5,
+                        egui::Stroke::new(2.0, egui::Color32::LIGHT_BLUE),
+                    );
+                }
+
+                for contact in &self.contacts {
+                    let point = self.camera.world_to_screen(rect, contact.point);
+                    painter.circle_filled(point, 3.0, egui::Color32::RED);
+                    // The normal is drawn pointing out of the rectangle towards the particle
+                    painter.line_segment(
+                        [
+                            point,
+                            point - egui::vec2(contact.normal.x, -contact.normal.y) * 15.0,
+                        ],
+                        egui::Stroke::new(1.5, egui::Color32::RED),
+                    );
+                }
+
+                if self.show_symbols {
+                    // Below this the symbols are too small to read
+                    const MIN_SYMBOL_RADIUS: f32 = 6.0;
+                    for particle in &self.scene.particles {
+                        let screen_radius = particle.radius(&self.config.custom_elements)
+                            * self.camera.zoom
+                            * rect.height()
+                            * 0.5;
+                        if screen_radius < MIN_SYMBOL_RADIUS {
+                            continue;
+                        }
+                        let symbol = particle.symbol(&self.config.custom_elements);
+                        // Sized so that the symbol's width stays inside the circle
+                        let font_size = screen_radius * 2.0
+                            / (symbol.chars().count().max(1) as f32 * 0.6 + 0.6);
+                        let color = particle.color(&self.config.custom_elements);
+                        let luminance = 0.299 * color.x + 0.587 * color.y + 0.114 * color.z;
+                        painter.text(
+                            self.camera.world_to_screen(rect, particle.position),
+                            egui::Align2::CENTER_CENTER,
+                            symbol,
+                            egui::FontId::proportional(font_size),
+                            if luminance > 0.5 {
+                                egui::Color32::BLACK
+                            } else {
+                                egui::Color32::WHITE
+                            },
+                        );
+                    }
+                }
+
+                if self.show_particle_ids || self.show_element_labels {
+                    // Labels on particles smaller than this on screen would just be clutter
+                    const MIN_LABEL_RADIUS: f32 = 12.0;
+                    for particle in &self.scene.particles {
+                        let screen_radius = particle.radius(&self.config.custom_elements)
+                            * self.camera.zoom
+                            * rect.height()
+                            * 0.5;
+                        if screen_radius < MIN_LABEL_RADIUS {
+                            continue;
+                        }
+                        let label = match (self.show_particle_ids, self.show_element_labels) {
+                            (true, true) => format!(
+                                "{}\n{}",
+                                particle.id,
+                                particle.element_name(&self.config.custom_elements)
+                            ),
+                            (true, false) => particle.id.to_string(),
+                            _ => particle
+                                .element_name(&self.config.custom_elements)
+                                .to_string(),
+                        };
+                        painter.text(
+                            self.camera.world_to_screen(rect, particle.position),
+                            egui::Align2::CENTER_CENTER,
+                            label,
+                            egui::FontId::monospace((screen_radius * 0.5).clamp(8.0, 16.0)),
+                            egui::Color32::WHITE,
+                        );
+                    }
+                }
+
+                if let Some((position, start)) = self.impulse_ring {
+                    const RING_DURATION: f32 = 0.3;
+                    let t = start.elapsed().as_secs_f32() / RING_DURATION;
+                    if t < 1.0 {
+                        painter.circle_stroke(
+                            self.camera.world_to_screen(rect, position),
+                            self.impulse_radius * t * self.camera.zoom * rect.height() * 0.5,
+                            egui::Stroke::new(
+                                2.0,
+                                egui::Color32::from_white_alpha(((1.0 - t) * 255.0) as u8),
+                            ),
+                        );
+                    } else {
+                        self.impulse_ring = None;
+                    }
+                }
+
+                if self.show_center_of_mass {
+                    if let Some((position, velocity)) =
+                        center_of_mass(&self.scene.particles, &self.config.custom_elements)
+                    {
+                        let screen_position = self.camera.world_to_screen(rect, position);
+                        let stroke = egui::Stroke::new(2.0, egui::Color32::YELLOW);
+                        painter.line_segment(
+                            [
+                                screen_position - egui::vec2(10.0, 0.0),
+                                screen_position + egui::vec2(10.0, 0.0),
+                            ],
+                            stroke,
+                        );
+                        painter.line_segment(
+                            [
+                                screen_position - egui::vec2(0.0, 10.0),
+                                screen_position + egui::vec2(0.0, 10.0),
+                            ],
+                            stroke,
+                        );
+                        painter.circle_stroke(screen_position, 5.0, stroke);
+                        painter.text(
+                            screen_position + egui::vec2(12.0, -12.0),
+                            egui::Align2::LEFT_BOTTOM,
+                            format!("v: ({:.3}, {:.3})", velocity.x, velocity.y),
+                            egui::FontId::monospace(12.0),
+                            egui::Color32::YELLOW,
+                        );
+                    }
+                }
+
+                if self.show_hud {
+                    let momentum: Vector2<f32> = self
+                        .scene
+                        .particles
+                        .iter()
+                        .map(|particle| particle.momentum(&self.config.custom_elements))
+                        .sum();
+                    let kinetic_energy: f32 = self
+                        .scene
+                        .particles
+                        .iter()
+                        .map(|particle| particle.kinetic_energy(&self.config.custom_elements))
+                        .sum();
+                    let align = self.hud_corner.align();
+                    painter.text(
+                        align.pos_in_rect(&rect.shrink(8.0)),
+                        align,
+                        format!(
+                            "Momentum: ({:.3}, {:.3})\nKinetic Energy: {:.3}\n\
+                             Particles: {}\ndt: {:.3}ms",
+                            momentum.x,
+                            momentum.y,
+                            kinetic_energy,
+                            self.scene.particles.len(),
+                            1000.0 * dt,
+                        ),
+                        egui::FontId::monospace(14.0),
+                        egui::Color32::WHITE,
+                    );
+                }
+
+                if self.show_scale_bar {
+                    // The bar is the largest 1, 2 or 5 times a power of ten that fits
+                    const MAX_BAR_WIDTH: f32 = 150.0;
+                    let pixels_per_unit = self.camera.zoom * rect.height() * 0.5;
+                    let max_length = MAX_BAR_WIDTH / pixels_per_unit;
+                    let exponent = max_length.log10().floor();
+                    let power = 10.0f32.powf(exponent);
+                    let length = [5.0, 2.0, 1.0]
+                        .into_iter()
+                        .map(|factor| factor * power)
+                        .find(|&length| length <= max_length)
+                        .unwrap_or(power);
+                    let width = length * pixels_per_unit;
+                    let bottom = rect.center_bottom() - egui::vec2(0.0, 16.0);
+                    let left = bottom - egui::vec2(width * 0.5, 0.0);
+                    let right = bottom + egui::vec2(width * 0.5, 0.0);
+                    let stroke = egui::Stroke::new(2.0, egui::Color32::WHITE);
+                    painter.line_segment([left, right], stroke);
+                    painter.line_segment([left, left - egui::vec2(0.0, 6.0)], stroke);
+                    painter.line_segment([right, right - egui::vec2(0.0, 6.0)], stroke);
+                    painter.text(
+                        bottom - egui::vec2(0.0, 4.0),
+                        egui::Align2::CENTER_BOTTOM,
+                        format!("{:.*}", (-exponent).max(0.0) as usize, length),
+                        egui::FontId::monospace(12.0),
+                        egui::Color32::WHITE,
+                    );
+                }
+
+                if let Some(minimap_rect) = minimap_rect {
+                    self.show_minimap(ui, rect, minimap_rect);
+                }
             });
 
         ctx.request_repaint();
@@ -341,6 +4500,49 @@ impl eframe::App for App {
 }
 
 fn main() {
+    if std::env::args().any(|arg| arg == LIST_PRESENT_MODES_ARG) {
+        match rendering::supported_present_modes() {
+            Ok(modes) => {
+                for mode in modes {
+                    println!("{}", present_mode_name(mode));
+                }
+            }
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut present_mode = std::env::args()
+        .find_map(|arg| {
+            let name = arg.strip_prefix("--present-mode=")?.to_owned();
+            let mode = PRESENT_MODES
+                .iter()
+                .find(|&&(_, mode_name, _)| mode_name == name)
+                .map(|&(mode, _, _)| mode);
+            if mode.is_none() {
+                println!("WARNING: Unknown present mode '{name}'");
+            }
+            mode
+        })
+        .unwrap_or(wgpu::PresentMode::AutoNoVsync);
+    // Only modes that might be unsupported are worth waiting for the probe at startup
+    let supported_present_modes = if ALWAYS_SUPPORTED_PRESENT_MODES.contains(&present_mode) {
+        SupportedPresentModes::Probing(std::thread::spawn(probe_present_modes))
+    } else {
+        let modes = probe_present_modes();
+        if !modes.contains(&present_mode) {
+            println!(
+                "WARNING: The present mode '{}' isn't supported, using fifo",
+                present_mode_name(present_mode)
+            );
+            present_mode = wgpu::PresentMode::Fifo;
+        }
+        SupportedPresentModes::Known(modes)
+    };
+
     eframe::run_native(
         "Rocket Simulator",
         NativeOptions {
@@ -348,12 +4550,16 @@ fn main() {
             renderer: Renderer::Wgpu,
             wgpu_options: WgpuConfiguration {
                 power_preference: wgpu::PowerPreference::HighPerformance,
-                present_mode: wgpu::PresentMode::AutoNoVsync,
+                present_mode,
                 ..Default::default()
             },
             ..Default::default()
         },
-        Box::new(|cc| Box::new(App::new(cc))),
+        Box::new(move |cc| Box::new(App::new(cc, present_mode, supported_present_modes))),
     )
-    .unwrap();
+    .unwrap_or_else(|error| {
+        eprintln!("Failed to start the simulation: {error}");
+        eprintln!("A GPU supported by wgpu (Vulkan, Metal, DirectX 12 or OpenGL) is required");
+        std::process::exit(1);
+    });
 }
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+
+use cgmath::{prelude::*, Vector2};
+
+use crate::{
+    physics::{Bond, Element, Particle, Rectangle, Scene, WallMaterial},
+    random::Rng,
+};
+
+pub type Preset = fn() -> Scene;
+
+pub const PRESETS: [(&str, Preset); 4] = [
+    ("Demo", demo),
+    ("Billiards", billiards),
+    ("Gas", gas),
+    ("Molecules", molecules),
+];
+
+/// Four walls of thickness 1 centered on the origin, the outer edges of the side walls
+/// span `height` and the top and bottom walls span `width`
+pub fn walls(width: f32, height: f32) -> Vec<Rectangle> {
+    vec![
+        Rectangle {
+            position: Vector2 {
+                x: -width * 0.5,
+                y: 0.0,
+            },
+            color: WallMaterial::Elastic.color(),
+            size: Vector2 { x: 1.0, y: height },
+            absorber: false,
+            material: WallMaterial::Elastic,
+            selected: false,
+        },
+        Rectangle {
+            position: Vector2 {
+                x: width * 0.5,
+                y: 0.0,
+            },
+            color: WallMaterial::Elastic.color(),
+            size: Vector2 { x: 1.0, y: height },
+            absorber: false,
+            material: WallMaterial::Elastic,
+            selected: false,
+        },
+        Rectangle {
+            position: Vector2 {
+                x: 0.0,
+                y: height * 0.5 - 0.5,
+            },
+            color: WallMaterial::Elastic.color(),
+            size: Vector2 { x: width, y: 1.0 },
+            absorber: false,
+            material: WallMaterial::Elastic,
+            selected: false,
+        },
+        Rectangle {
+            position: Vector2 {
+                x: 0.0,
+                y: -height * 0.5 + 0.5,
+            },
+            color: WallMaterial::Elastic.color(),
+            size: Vector2 { x: width, y: 1.0 },
+            absorber: false,
+            material: WallMaterial::Elastic,
+            selected: false,
+        },
+    ]
+}
+
+/// Four walls whose outer edges are the edges of the box from `min` to `max`, so all of
+/// them stay visible when the box is the view. They're 1 thick unless the box is tiny.
+pub fn walls_inside(min: Vector2<f32>, max: Vector2<f32>) -> Vec<Rectangle> {
+    let size = max - min;
+    let center = (min + max) * 0.5;
+    let thickness = (0.1 * size.x.min(size.y)).min(1.0);
+    let wall = |position: Vector2<f32>, size: Vector2<f32>| Rectangle {
+        position,
+        color: WallMaterial::Elastic.color(),
+        size,
+        absorber: false,
+        material: WallMaterial::Elastic,
+        selected: false,
+    };
+    vec![
+        wall(
+            Vector2::new(min.x + thickness * 0.5, center.y),
+            Vector2::new(thickness, size.y),
+        ),
+        wall(
+            Vector2::new(max.x - thickness * 0.5, center.y),
+            Vector2::new(thickness, size.y),
+        ),
+        wall(
+            Vector2::new(center.x, max.y - thickness * 0.5),
+            Vector2::new(size.x, thickness),
+        ),
+        wall(
+            Vector2::new(center.x, min.y + thickness * 0.5),
+            Vector2::new(size.x, thickness),
+        ),
+    ]
+}
+
+/// Adds `rows * cols` particles at rest on a grid starting at `origin`, optionally bonding
+/// each one to its horizontal and vertical neighbors with the spacing as the rest length
+pub fn spawn_lattice(
+    scene: &mut Scene,
+    origin: Vector2<f32>,
+    rows: usize,
+    cols: usize,
+    spacing: f32,
+    element: Element,
+    bond_neighbors: bool,
+) {
+    let first = scene.particles.len();
+    for row in 0..rows {
+        for col in 0..cols {
+            scene.particles.push(Particle::new(
+                origin
+                    + Vector2 {
+                        x: col as f32,
+                        y: row as f32,
+                    } * spacing,
+                Vector2::zero(),
+                element,
+            ));
+            if bond_neighbors {
+                let i = first + row * cols + col;
+                if col > 0 {
+                    scene.bonds.insert(
+                        (i - 1, i),
+                        Bond {
+                            rest_length: spacing,
+                        },
+                    );
+                }
+                if row > 0 {
+                    scene.bonds.insert(
+                        (i - cols, i),
+                        Bond {
+                            rest_length: spacing,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Offsets of the particles on the outer ring of a lattice spawned by `spawn_lattice`
+pub fn lattice_boundary(rows: usize, cols: usize) -> Vec<usize> {
+    (0..rows * cols)
+        .filter(|i| {
+            let (row, col) = (i / cols, i % cols);
+            row == 0 || col == 0 || row == rows - 1 || col == cols - 1
+        })
+        .collect()
+}
+
+pub fn demo() -> Scene {
+    Scene {
+        particles: vec![
+            Particle::new(
+                Vector2 { x: 3.0, y: 0.0 },
+                Vector2 { x: -1.0, y: 0.0 },
+                Element::Oxygen,
+            ),
+            Particle::new(
+                Vector2 { x: -3.0, y: 0.0 },
+                Vector2 { x: 0.1, y: 0.0 },
+                Element::Hydrogen,
+            ),
+            Particle::new(
+                Vector2 { x: -6.0, y: 0.5 },
+                Vector2 { x: 40.0, y: 10.0 },
+                Element::Hydrogen,
+            ),
+        ],
+        bonds: BTreeMap::new(),
+        rectangles: walls(30.0, 16.0),
+    }
+}
+
+pub fn billiards() -> Scene {
+    let radius = Particle::new(Vector2::zero(), Vector2::zero(), Element::Oxygen).radius(&[]);
+    let mut particles = vec![Particle::new(
+        Vector2 { x: -20.0, y: 0.0 },
+        Vector2 { x: 30.0, y: 0.0 },
+        Element::Oxygen,
+    )];
+    for row in 0..5 {
+        for i in 0..=row {
+            particles.push(Particle::new(
+                Vector2 {
+                    x: 5.0 + row as f32 * radius * 3.0f32.sqrt(),
+                    y: (i as f32 - row as f32 * 0.5) * radius * 2.0,
+                },
+                Vector2::zero(),
+                Element::Oxygen,
+            ));
+        }
+    }
+    Scene {
+        particles,
+        bonds: BTreeMap::new(),
+        rectangles: walls(60.0, 34.0),
+    }
+}
+
+pub fn gas() -> Scene {
+    let mut rng = Rng::new(0);
+    let particles = (0..100)
+        .map(|i| {
+            Particle::new(
+                Vector2 {
+                    x: rng.range(-18.0, 18.0),
+                    y: rng.range(-10.0, 10.0),
+                },
+                Vector2 {
+                    x: rng.range(-5.0, 5.0),
+                    y: rng.range(-5.0, 5.0),
+                },
+                if i % 5 == 0 {
+                    Element::Oxygen
+                } else {
+                    Element::Hydrogen
+                },
+            )
+        })
+        .collect();
+    Scene {
+        particles,
+        bonds: BTreeMap::new(),
+        rectangles: walls(40.0, 24.0),
+    }
+}
+
+pub fn molecules() -> Scene {
+    let mut scene = Scene {
+        particles: vec![],
+        bonds: BTreeMap::new(),
+        rectangles: walls(40.0, 24.0),
+    };
+    for (i, &(x, y)) in [(-10.0, -4.0), (0.0, 4.0), (10.0, -4.0)].iter().enumerate() {
+        let velocity = Vector2 {
+            x: (i as f32 - 1.0) * 2.0,
+            y: 1.0,
+        };
+        spawn_water(&mut scene, Vector2 { x, y }, velocity);
+    }
+    scene
+}
+
+/// Adds an oxygen bonded to two hydrogens at the angle of a water molecule
+pub fn spawn_water(scene: &mut Scene, position: Vector2<f32>, velocity: Vector2<f32>) {
+    let oxygen = scene.particles.len();
+    scene
+        .particles
+        .push(Particle::new(position, velocity, Element::Oxygen));
+    for angle in [-52.25f32, 52.25] {
+        let direction = Vector2 {
+            x: angle.to_radians().cos(),
+            y: angle.to_radians().sin(),
+        };
+        let hydrogen = Particle::new(position + direction * 3.2, velocity, Element::Hydrogen);
+        let rest_length = hydrogen.position.distance(scene.particles[oxygen].position);
+        scene
+            .bonds
+            .insert((oxygen, scene.particles.len()), Bond { rest_length });
+        scene.particles.push(hydrogen);
+    }
+}
+
+/// Adds `count` particles at random positions in the box between `min` and `max`, moving
+/// in random directions at up to `max_speed`. One in five is oxygen like in the gas preset.
+pub fn spawn_random(
+    scene: &mut Scene,
+    rng: &mut Rng,
+    count: usize,
+    min: Vector2<f32>,
+    max: Vector2<f32>,
+    max_speed: f32,
+) {
+    for i in 0..count {
+        let angle = rng.range(0.0, std::f32::consts::TAU);
+        let speed = rng.range(0.0, max_speed);
+        scene.particles.push(Particle::new(
+            Vector2 {
+                x: rng.range(min.x, max.x),
+                y: rng.range(min.y, max.y),
+            },
+            Vector2 {
+                x: angle.cos(),
+                y: angle.sin(),
+            } * speed,
+            if i % 5 == 0 {
+                Element::Oxygen
+            } else {
+                Element::Hydrogen
+            },
+        ));
+    }
+}
+
+/// Gives every particle that isn't frozen a velocity whose components are normally
+/// distributed with `std_dev`, so a gas starts close to the Maxwell-Boltzmann distribution
+pub fn randomize_velocities(scene: &mut Scene, rng: &mut Rng, std_dev: f32) {
+    for particle in scene
+        .particles
+        .iter_mut()
+        .filter(|particle| !particle.frozen)
+    {
+        particle.velocity = Vector2 {
+            x: rng.normal(0.0, std_dev),
+            y: rng.normal(0.0, std_dev),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn randomized_velocities_have_the_requested_variance() {
+        let mut scene = Scene {
+            particles: vec![],
+            bonds: BTreeMap::new(),
+            rectangles: vec![],
+        };
+        spawn_random(
+            &mut scene,
+            &mut Rng::new(1),
+            10000,
+            Vector2::new(-50.0, -50.0),
+            Vector2::new(50.0, 50.0),
+            0.0,
+        );
+        randomize_velocities(&mut scene, &mut Rng::new(7), 3.0);
+        let count = scene.particles.len() as f32;
+        for component in [|v: Vector2<f32>| v.x, |v: Vector2<f32>| v.y] {
+            let values: Vec<f32> = scene
+                .particles
+                .iter()
+                .map(|particle| component(particle.velocity))
+                .collect();
+            let mean = values.iter().sum::<f32>() / count;
+            let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+            assert!(mean.abs() < 0.1, "{mean}");
+            assert!((variance - 9.0).abs() < 0.5, "{variance}");
+        }
+    }
+
+    #[test]
+    fn lattice_boundary_is_the_outer_ring() {
+        assert_eq!(lattice_boundary(3, 4), vec![0, 1, 2, 3, 4, 7, 8, 9, 10, 11]);
+        assert_eq!(lattice_boundary(1, 3), vec![0, 1, 2]);
+        assert_eq!(lattice_boundary(5, 5).len(), 16);
+    }
+
+    #[test]
+    fn lattice_bonds_nearest_neighbors() {
+        let mut scene = molecules();
+        let existing_particles = scene.particles.len();
+        let existing_bonds = scene.bonds.len();
+        spawn_lattice(
+            &mut scene,
+            Vector2::new(-5.0, -5.0),
+            3,
+            4,
+            2.0,
+            Element::Hydrogen,
+            true,
+        );
+        assert_eq!(scene.particles.len(), existing_particles + 3 * 4);
+        assert_eq!(scene.bonds.len(), existing_bonds + 3 * 3 + 2 * 4);
+        for (&(a, b), bond) in &scene.bonds {
+            if a >= existing_particles {
+                let distance = scene.particles[a]
+                    .position
+                    .distance(scene.particles[b].position);
+                assert!((distance - bond.rest_length).abs() < 1e-5);
+                assert_eq!(bond.rest_length, 2.0);
+            }
+        }
+
+        spawn_lattice(
+            &mut scene,
+            Vector2::zero(),
+            2,
+            2,
+            1.0,
+            Element::Oxygen,
+            false,
+        );
+        assert_eq!(scene.bonds.len(), existing_bonds + 3 * 3 + 2 * 4);
+    }
+
+    #[test]
+    fn walls_inside_enclose_the_box() {
+        let min = Vector2::new(-16.0, -3.0);
+        let max = Vector2::new(4.0, 9.0);
+        let walls = walls_inside(min, max);
+        assert_eq!(walls.len(), 4);
+        for wall in &walls {
+            let (low, high) = (
+                wall.position - wall.size * 0.5,
+                wall.position + wall.size * 0.5,
+            );
+            assert!(low.x >= min.x - 1e-5 && low.y >= min.y - 1e-5);
+            assert!(high.x <= max.x + 1e-5 && high.y <= max.y + 1e-5);
+        }
+        // A particle anywhere inside can't get out, whichever way it moves
+        let mut scene = Scene {
+            particles: vec![Particle::new(
+                Vector2::new(-6.0, 3.0),
+                Vector2::new(30.0, 20.0),
+                Element::Hydrogen,
+            )],
+            bonds: BTreeMap::new(),
+            rectangles: walls,
+        };
+        let config = crate::physics::SimulationConfig::default();
+        for _ in 0..600 {
+            scene.step(&config, 1.0 / 120.0);
+            let position = scene.particles[0].position;
+            assert!(position.x > min.x && position.x < max.x);
+            assert!(position.y > min.y && position.y < max.y);
+        }
+    }
+
+    #[test]
+    fn random_particles_stay_in_the_box() {
+        let mut scene = gas();
+        let existing_particles = scene.particles.len();
+        let min = Vector2::new(-3.0, 1.0);
+        let max = Vector2::new(5.0, 2.0);
+        spawn_random(&mut scene, &mut Rng::new(7), 50, min, max, 4.0);
+        assert_eq!(scene.particles.len(), existing_particles + 50);
+        for particle in &scene.particles[existing_particles..] {
+            assert!(particle.position.x >= min.x && particle.position.x <= max.x);
+            assert!(particle.position.y >= min.y && particle.position.y <= max.y);
+            assert!(particle.velocity.magnitude() <= 4.0 + 1e-5);
+        }
+    }
+}
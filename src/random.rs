@@ -0,0 +1,37 @@
+/// A small deterministic random number generator, so seeded scenes are reproducible
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        // PCG multiplier and increment
+        self.state = self
+            .state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.state >> 32) as u32
+    }
+
+    /// Returns a number in `0.0..1.0`
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + self.next_f32() * (max - min)
+    }
+
+    /// Normally distributed around `mean`, using the Box-Muller transform
+    pub fn normal(&mut self, mean: f32, std_dev: f32) -> f32 {
+        // 1 - x keeps the logarithm away from 0
+        let radius = (-2.0 * (1.0 - self.next_f32()).ln()).sqrt();
+        let angle = self.next_f32() * std::f32::consts::TAU;
+        mean + std_dev * radius * angle.cos()
+    }
+}
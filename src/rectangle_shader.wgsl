@@ -13,6 +13,7 @@ struct Camera {
     position: vec2<f32>,
     aspect: f32,
     zoom: f32,
+    edge_softness: f32,
 };
 
 @group(0)
@@ -23,6 +24,7 @@ struct Rectangle {
     position: vec2<f32>,
     color: vec3<f32>,
     size: vec2<f32>,
+    alpha: f32,
 };
 
 @group(1)
@@ -48,5 +50,6 @@ fn vertex(input: VertexInput) -> VertexOutput {
 
 @fragment
 fn pixel(input: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(rectangles[input.rectangle_index].color, 1.0);
+    let rectangle = rectangles[input.rectangle_index];
+    return vec4<f32>(rectangle.color, rectangle.alpha);
 }
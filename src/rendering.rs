@@ -1,65 +1,372 @@
-use cgmath::{Vector2, Vector3};
+use std::future::Future;
+
+use cgmath::Vector2;
 use eframe::{
     egui_wgpu::{self, CallbackTrait},
     wgpu::{self, include_wgsl},
 };
 use encase::{ShaderSize, ShaderType, StorageBuffer, UniformBuffer};
 
-#[derive(ShaderType)]
-pub struct GpuCamera {
-    pub position: Vector2<f32>,
-    pub aspect: f32,
-    pub zoom: f32,
-}
+pub use gpu_types::{GpuCamera, GpuCircle, GpuRectangle};
+use gpu_types::{GpuCircles, GpuRectangles};
+
+// `#[derive(ShaderType)]` generates unused `check` functions on newer compilers
+#[allow(dead_code)]
+mod gpu_types {
+    use cgmath::{Vector2, Vector3};
+    use encase::ShaderType;
+
+    #[derive(ShaderType)]
+    pub struct GpuCamera {
+        pub position: Vector2<f32>,
+        pub aspect: f32,
+        pub zoom: f32,
+        /// Width in pixels over which the rim of the circles fades out, 0 for hard edges.
+        /// The fade costs a blend per pixel and a little more fragment work, but unlike MSAA
+        /// it doesn't render more samples, and wider rims look blurry rather than smoother
+        pub edge_softness: f32,
+    }
 
-#[derive(ShaderType)]
-pub struct GpuCircle {
-    pub position: Vector2<f32>,
-    pub color: Vector3<f32>,
-    pub radius: f32,
-}
+    #[derive(ShaderType)]
+    pub struct GpuCircle {
+        pub position: Vector2<f32>,
+        pub color: Vector3<f32>,
+        pub semi_axes: Vector2<f32>,
+        /// Counterclockwise rotation of the first semi axis in radians
+        pub orientation: f32,
+        pub sprite_index: u32,
+        pub outline: u32,
+        /// 0 is invisible and 1 is opaque
+        pub alpha: f32,
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuCircles<'a> {
+        #[size(runtime)]
+        pub circles: &'a [GpuCircle],
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuRectangle {
+        pub position: Vector2<f32>,
+        pub color: Vector3<f32>,
+        pub size: Vector2<f32>,
+        /// 0 is invisible and 1 is opaque
+        pub alpha: f32,
+    }
 
-#[derive(ShaderType)]
-struct GpuCircles<'a> {
-    #[size(runtime)]
-    circles: &'a [GpuCircle],
+    #[derive(ShaderType)]
+    pub struct GpuRectangles<'a> {
+        #[size(runtime)]
+        pub rectangles: &'a [GpuRectangle],
+    }
 }
 
-#[derive(ShaderType)]
-pub struct GpuRectangle {
-    pub position: Vector2<f32>,
-    pub color: Vector3<f32>,
-    pub size: Vector2<f32>,
+impl GpuCircle {
+    /// Draws the circle with a flat color instead of a sprite from the atlas
+    pub const NO_SPRITE: u32 = u32::MAX;
+
+    pub const NO_OUTLINE: u32 = 0;
+    /// A faint ring around the particle under the cursor
+    pub const HOVER_OUTLINE: u32 = 1;
+    /// Takes precedence over the hover outline when the particle is both hovered and selected
+    pub const SELECTED_OUTLINE: u32 = 2;
 }
 
-#[derive(ShaderType)]
-struct GpuRectangles<'a> {
-    #[size(runtime)]
-    rectangles: &'a [GpuRectangle],
+const SPRITE_SIZE: u32 = 64;
+const SPRITE_COUNT: u32 = 2;
+
+/// Generates a horizontal strip of greyscale sprites that get tinted by the circle color
+fn create_sprite_atlas() -> Vec<u8> {
+    let width = SPRITE_SIZE * SPRITE_COUNT;
+    let mut pixels = Vec::with_capacity((width * SPRITE_SIZE * 4) as usize);
+    for y in 0..SPRITE_SIZE {
+        for x in 0..width {
+            let sprite = x / SPRITE_SIZE;
+            let uv = Vector2 {
+                x: (x % SPRITE_SIZE) as f32 / SPRITE_SIZE as f32 * 2.0 - 1.0,
+                y: y as f32 / SPRITE_SIZE as f32 * 2.0 - 1.0,
+            };
+            let nuclei: &[Vector2<f32>] = match sprite {
+                0 => &[Vector2 { x: 0.0, y: 0.0 }],
+                _ => &[
+                    Vector2 { x: -0.2, y: -0.2 },
+                    Vector2 { x: 0.2, y: -0.2 },
+                    Vector2 { x: -0.2, y: 0.2 },
+                    Vector2 { x: 0.2, y: 0.2 },
+                ],
+            };
+            let in_nucleus = nuclei
+                .iter()
+                .any(|nucleus| (uv.x - nucleus.x).powi(2) + (uv.y - nucleus.y).powi(2) < 0.2 * 0.2);
+            let brightness = if in_nucleus {
+                255
+            } else {
+                (255.0 * (1.0 - 0.5 * (uv.x * uv.x + uv.y * uv.y).sqrt())) as u8
+            };
+            pixels.extend_from_slice(&[brightness, brightness, brightness, 255]);
+        }
+    }
+    pixels
 }
 
 struct RenderState {
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    circle_render_pipeline: wgpu::RenderPipeline,
+    /// `None` when the shaders failed to compile
+    render_pipelines: Option<RenderPipelines>,
+    /// Kept to rebuild the pipelines when the shaders are reloaded
+    #[cfg_attr(not(feature = "shader-hot-reload"), allow(dead_code))]
+    circle_pipeline_layout: wgpu::PipelineLayout,
     circle_buffer_size: wgpu::BufferAddress,
     circle_buffer: wgpu::Buffer,
     circle_bind_group_layout: wgpu::BindGroupLayout,
     circle_bind_group: wgpu::BindGroup,
-    rectangle_render_pipeline: wgpu::RenderPipeline,
+    sprite_texture_view: wgpu::TextureView,
+    sprite_sampler: wgpu::Sampler,
+    /// Kept to rebuild the pipelines when the shaders are reloaded
+    #[cfg_attr(not(feature = "shader-hot-reload"), allow(dead_code))]
+    rectangle_pipeline_layout: wgpu::PipelineLayout,
     rectangle_buffer_size: wgpu::BufferAddress,
     rectangle_buffer: wgpu::Buffer,
     rectangle_bind_group_layout: wgpu::BindGroupLayout,
     rectangle_bind_group: wgpu::BindGroup,
+    /// The format the pipelines were created for, which offscreen targets have to match
+    target_format: wgpu::TextureFormat,
+}
+
+struct RenderPipelines {
+    circle: wgpu::RenderPipeline,
+    rectangle: wgpu::RenderPipeline,
+}
+
+fn create_circle_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    circle_buffer: &wgpu::Buffer,
+    sprite_texture_view: &wgpu::TextureView,
+    sprite_sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Circle Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: circle_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(sprite_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sprite_sampler),
+            },
+        ],
+    })
+}
+
+fn create_circle_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    target_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Circle Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vertex",
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "pixel",
+            // Blended so that the soft rims and translucent circles show what's behind them
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+fn create_rectangle_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    target_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Rectangle Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vertex",
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "pixel",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+/// Runs `f` and returns the first validation error it caused instead of letting wgpu panic
+fn capture_errors<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    // Native backends report errors right away, so the scope is ready on the first poll
+    let error = std::pin::pin!(device.pop_error_scope())
+        .poll(&mut std::task::Context::from_waker(std::task::Waker::noop()));
+    match error {
+        std::task::Poll::Ready(Some(error)) => Err(error.to_string()),
+        _ => Ok(value),
+    }
+}
+
+/// The present modes a window surface supports on this system, found with a hidden window.
+/// winit only allows one event loop per process, so this can't run in the process eframe
+/// creates its window in.
+pub fn supported_present_modes() -> Result<Vec<wgpu::PresentMode>, String> {
+    let event_loop = winit::event_loop::EventLoop::new();
+    let window = winit::window::WindowBuilder::new()
+        .with_visible(false)
+        .build(&event_loop)
+        .map_err(|error| error.to_string())?;
+    // The same backends and adapter eframe picks
+    let configuration = egui_wgpu::WgpuConfiguration::default();
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: configuration.supported_backends,
+        dx12_shader_compiler: Default::default(),
+    });
+    let surface = unsafe { instance.create_surface(&window) }.map_err(|error| error.to_string())?;
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: false,
+        compatible_surface: Some(&surface),
+    }))
+    .ok_or("no adapter can present to the window")?;
+    Ok(surface.get_capabilities(&adapter).present_modes)
 }
 
-pub fn create_render_state(cc: &eframe::CreationContext) {
+/// Compiles both shaders and returns the first compile or validation error instead of
+/// panicking on it
+fn create_render_pipelines(
+    device: &wgpu::Device,
+    circle_pipeline_layout: &wgpu::PipelineLayout,
+    rectangle_pipeline_layout: &wgpu::PipelineLayout,
+    circle_shader: wgpu::ShaderModuleDescriptor,
+    rectangle_shader: wgpu::ShaderModuleDescriptor,
+    target_format: wgpu::TextureFormat,
+) -> Result<RenderPipelines, String> {
+    capture_errors(device, || {
+        let circle_shader = device.create_shader_module(circle_shader);
+        let rectangle_shader = device.create_shader_module(rectangle_shader);
+        RenderPipelines {
+            circle: create_circle_render_pipeline(
+                device,
+                circle_pipeline_layout,
+                &circle_shader,
+                target_format,
+            ),
+            rectangle: create_rectangle_render_pipeline(
+                device,
+                rectangle_pipeline_layout,
+                &rectangle_shader,
+                target_format,
+            ),
+        }
+    })
+}
+
+/// Whether there are pipelines to draw with, which stay around when a reload fails
+pub fn has_render_pipelines(egui_render_state: &egui_wgpu::RenderState) -> bool {
+    let renderer = egui_render_state.renderer.read();
+    renderer
+        .callback_resources
+        .get::<RenderState>()
+        .is_some_and(|render_state| render_state.render_pipelines.is_some())
+}
+
+/// Rebuilds the pipelines from the shader files in `src`, keeping the current pipelines
+/// when either shader doesn't compile
+#[cfg(feature = "shader-hot-reload")]
+pub fn reload_shaders(egui_render_state: &egui_wgpu::RenderState) -> Result<(), String> {
+    let read = |name: &str| {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join(name);
+        std::fs::read_to_string(&path).map_err(|error| format!("{}: {error}", path.display()))
+    };
+    let circle_source = read("circle_shader.wgsl")?;
+    let rectangle_source = read("rectangle_shader.wgsl")?;
+
+    let device = &egui_render_state.device;
+    let mut renderer = egui_render_state.renderer.write();
+    let render_state: &mut RenderState = renderer.callback_resources.get_mut().unwrap();
+    render_state.render_pipelines = Some(create_render_pipelines(
+        device,
+        &render_state.circle_pipeline_layout,
+        &render_state.rectangle_pipeline_layout,
+        wgpu::ShaderModuleDescriptor {
+            label: Some("circle_shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(circle_source.into()),
+        },
+        wgpu::ShaderModuleDescriptor {
+            label: Some("rectangle_shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(rectangle_source.into()),
+        },
+        render_state.target_format,
+    )?);
+    Ok(())
+}
+
+/// Fails if eframe couldn't set up wgpu, e.g. on machines without a supported GPU.
+/// Returns why the shaders didn't compile if they didn't, in which case nothing gets drawn.
+pub fn create_render_state(cc: &eframe::CreationContext) -> Result<Option<String>, &'static str> {
+    let Some(render_state) = cc.wgpu_render_state.as_ref() else {
+        return Err("The wgpu renderer isn't available");
+    };
     let egui_wgpu::RenderState {
         ref device,
+        ref queue,
         target_format,
         ref renderer,
         ..
-    } = *cc.wgpu_render_state.as_ref().unwrap();
+    } = *render_state;
 
     let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("Camera Buffer"),
@@ -73,7 +380,7 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
             label: Some("Camera Bind Group Layout"),
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -100,31 +407,84 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
         mapped_at_creation: false,
     });
 
+    let sprite_texture_size = wgpu::Extent3d {
+        width: SPRITE_SIZE * SPRITE_COUNT,
+        height: SPRITE_SIZE,
+        depth_or_array_layers: 1,
+    };
+    let sprite_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Sprite Texture"),
+        size: sprite_texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &sprite_texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        &create_sprite_atlas(),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * sprite_texture_size.width),
+            rows_per_image: Some(sprite_texture_size.height),
+        },
+        sprite_texture_size,
+    );
+    let sprite_texture_view = sprite_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sprite_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Sprite Sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
     let circle_bind_group_layout =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Circle Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: Some(GpuCircles::min_size()),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(GpuCircles::min_size()),
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
         });
 
-    let circle_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Circle Bind Group"),
-        layout: &circle_bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: circle_buffer.as_entire_binding(),
-        }],
-    });
-
-    let circle_shader = device.create_shader_module(include_wgsl!("./circle_shader.wgsl"));
+    let circle_bind_group = create_circle_bind_group(
+        device,
+        &circle_bind_group_layout,
+        &circle_buffer,
+        &sprite_texture_view,
+        &sprite_sampler,
+    );
 
     let circle_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Circle Pipeline Layout"),
@@ -132,33 +492,6 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
         push_constant_ranges: &[],
     });
 
-    let circle_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Circle Render Pipeline"),
-        layout: Some(&circle_pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &circle_shader,
-            entry_point: "vertex",
-            buffers: &[],
-        },
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleStrip,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Cw,
-            cull_mode: None,
-            unclipped_depth: false,
-            polygon_mode: wgpu::PolygonMode::Fill,
-            conservative: false,
-        },
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        fragment: Some(wgpu::FragmentState {
-            module: &circle_shader,
-            entry_point: "pixel",
-            targets: &[Some(target_format.into())],
-        }),
-        multiview: None,
-    });
-
     let rectangle_buffer_size = GpuRectangles::min_size().get();
     let rectangle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("Rectangle Buffer"),
@@ -191,8 +524,6 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
         }],
     });
 
-    let rectangle_shader = device.create_shader_module(include_wgsl!("./rectangle_shader.wgsl"));
-
     let rectangle_pipeline_layout =
         device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Rectangle Pipeline Layout"),
@@ -200,138 +531,149 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
             push_constant_ranges: &[],
         });
 
-    let rectangle_render_pipeline =
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Rectangle Render Pipeline"),
-            layout: Some(&rectangle_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &rectangle_shader,
-                entry_point: "vertex",
-                buffers: &[],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Cw,
-                cull_mode: None,
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            fragment: Some(wgpu::FragmentState {
-                module: &rectangle_shader,
-                entry_point: "pixel",
-                targets: &[Some(target_format.into())],
-            }),
-            multiview: None,
-        });
+    // Without pipelines only the UI is drawn, which beats crashing on drivers that reject
+    // the shaders
+    let (render_pipelines, shader_error) = match create_render_pipelines(
+        device,
+        &circle_pipeline_layout,
+        &rectangle_pipeline_layout,
+        include_wgsl!("./circle_shader.wgsl"),
+        include_wgsl!("./rectangle_shader.wgsl"),
+        target_format,
+    ) {
+        Ok(render_pipelines) => (Some(render_pipelines), None),
+        Err(error) => (None, Some(error)),
+    };
 
     renderer.write().callback_resources.insert(RenderState {
         camera_buffer,
         camera_bind_group,
-        circle_render_pipeline,
+        render_pipelines,
+        circle_pipeline_layout,
         circle_buffer_size,
         circle_buffer,
         circle_bind_group_layout,
         circle_bind_group,
-        rectangle_render_pipeline,
+        sprite_texture_view,
+        sprite_sampler,
+        rectangle_pipeline_layout,
         rectangle_buffer_size,
         rectangle_buffer,
         rectangle_bind_group_layout,
         rectangle_bind_group,
+        target_format,
     });
+    Ok(shader_error)
 }
 
-pub struct RenderCallback {
-    pub camera: GpuCamera,
-    pub circles: Vec<GpuCircle>,
-    pub rectangles: Vec<GpuRectangle>,
-}
-
-impl CallbackTrait for RenderCallback {
-    fn prepare(
-        &self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        _egui_encoder: &mut wgpu::CommandEncoder,
-        callback_resources: &mut eframe::egui_wgpu::CallbackResources,
-    ) -> Vec<wgpu::CommandBuffer> {
-        let render_state: &mut RenderState = callback_resources.get_mut().unwrap();
-
+impl RenderState {
+    fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, callback: &RenderCallback) {
         {
             let mut camera_buffer = UniformBuffer::new([0u8; GpuCamera::SHADER_SIZE.get() as _]);
-            camera_buffer.write(&self.camera).unwrap();
-            queue.write_buffer(&render_state.camera_buffer, 0, &camera_buffer.into_inner());
+            camera_buffer.write(&callback.camera).unwrap();
+            queue.write_buffer(&self.camera_buffer, 0, &camera_buffer.into_inner());
         }
 
         {
             let mut circle_buffer = StorageBuffer::new(vec![]);
             circle_buffer
                 .write(&GpuCircles {
-                    circles: &self.circles,
+                    circles: &callback.circles,
                 })
                 .unwrap();
             let circle_buffer = circle_buffer.into_inner();
 
-            if circle_buffer.len() as wgpu::BufferAddress > render_state.circle_buffer_size {
-                render_state.circle_buffer_size = circle_buffer.len() as _;
+            if circle_buffer.len() as wgpu::BufferAddress > self.circle_buffer_size {
+                self.circle_buffer_size = circle_buffer.len() as _;
 
-                render_state.circle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                self.circle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
                     label: Some("Circle Buffer"),
-                    size: render_state.circle_buffer_size,
+                    size: self.circle_buffer_size,
                     usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
                     mapped_at_creation: false,
                 });
 
-                render_state.circle_bind_group =
-                    device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        label: Some("Circle Bind Group"),
-                        layout: &render_state.circle_bind_group_layout,
-                        entries: &[wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: render_state.circle_buffer.as_entire_binding(),
-                        }],
-                    });
+                self.circle_bind_group = create_circle_bind_group(
+                    device,
+                    &self.circle_bind_group_layout,
+                    &self.circle_buffer,
+                    &self.sprite_texture_view,
+                    &self.sprite_sampler,
+                );
             }
 
-            queue.write_buffer(&render_state.circle_buffer, 0, &circle_buffer);
+            queue.write_buffer(&self.circle_buffer, 0, &circle_buffer);
         }
 
         {
             let mut rectangle_buffer = StorageBuffer::new(vec![]);
             rectangle_buffer
                 .write(&GpuRectangles {
-                    rectangles: &self.rectangles,
+                    rectangles: &callback.rectangles,
                 })
                 .unwrap();
             let rectangle_buffer = rectangle_buffer.into_inner();
 
-            if rectangle_buffer.len() as wgpu::BufferAddress > render_state.rectangle_buffer_size {
-                render_state.rectangle_buffer_size = rectangle_buffer.len() as _;
+            if rectangle_buffer.len() as wgpu::BufferAddress > self.rectangle_buffer_size {
+                self.rectangle_buffer_size = rectangle_buffer.len() as _;
 
-                render_state.rectangle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                self.rectangle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
                     label: Some("Rectangle Buffer"),
-                    size: render_state.rectangle_buffer_size,
+                    size: self.rectangle_buffer_size,
                     usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
                     mapped_at_creation: false,
                 });
 
-                render_state.rectangle_bind_group =
-                    device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        label: Some("Rectangle Bind Group"),
-                        layout: &render_state.rectangle_bind_group_layout,
-                        entries: &[wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: render_state.rectangle_buffer.as_entire_binding(),
-                        }],
-                    });
+                self.rectangle_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Rectangle Bind Group"),
+                    layout: &self.rectangle_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.rectangle_buffer.as_entire_binding(),
+                    }],
+                });
             }
 
-            queue.write_buffer(&render_state.rectangle_buffer, 0, &rectangle_buffer);
+            queue.write_buffer(&self.rectangle_buffer, 0, &rectangle_buffer);
         }
+    }
+
+    /// Translucent shapes blend with whatever was drawn before them without depth sorting,
+    /// so all circles are drawn in order and then all rectangles in order on top of them
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, callback: &RenderCallback) {
+        let Some(ref render_pipelines) = self.render_pipelines else {
+            return;
+        };
+        render_pass.set_pipeline(&render_pipelines.circle);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.circle_bind_group, &[]);
+        render_pass.draw(0..4, 0..callback.circles.len() as _);
+
+        render_pass.set_pipeline(&render_pipelines.rectangle);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.rectangle_bind_group, &[]);
+        render_pass.draw(0..4, 0..callback.rectangles.len() as _);
+    }
+}
 
+pub struct RenderCallback {
+    pub camera: GpuCamera,
+    /// Drawn back to front, so translucent circles should come after what they cover
+    pub circles: Vec<GpuCircle>,
+    /// Drawn back to front on top of all the circles
+    pub rectangles: Vec<GpuRectangle>,
+}
+
+impl CallbackTrait for RenderCallback {
+    fn prepare(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _egui_encoder: &mut wgpu::CommandEncoder,
+        callback_resources: &mut eframe::egui_wgpu::CallbackResources,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let render_state: &mut RenderState = callback_resources.get_mut().unwrap();
+        render_state.upload(device, queue, self);
         Vec::new()
     }
 
@@ -352,15 +694,109 @@ impl CallbackTrait for RenderCallback {
         callback_resources: &'a eframe::egui_wgpu::CallbackResources,
     ) {
         let render_state: &RenderState = callback_resources.get().unwrap();
+        render_state.draw(render_pass, self);
+    }
+}
 
-        render_pass.set_pipeline(&render_state.circle_render_pipeline);
-        render_pass.set_bind_group(0, &render_state.camera_bind_group, &[]);
-        render_pass.set_bind_group(1, &render_state.circle_bind_group, &[]);
-        render_pass.draw(0..4, 0..self.circles.len() as _);
+/// Draws the scene into an offscreen texture of the given size and saves it as a PNG,
+/// independently of the window
+pub fn render_to_png(
+    egui_render_state: &egui_wgpu::RenderState,
+    callback: &RenderCallback,
+    width: u32,
+    height: u32,
+    path: &std::path::Path,
+) -> Result<(), String> {
+    let egui_wgpu::RenderState {
+        ref device,
+        ref queue,
+        ref renderer,
+        ..
+    } = *egui_render_state;
+    let mut renderer = renderer.write();
+    let render_state: &mut RenderState = renderer.callback_resources.get_mut().unwrap();
+    render_state.upload(device, queue, callback);
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Recording Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: render_state.target_format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    // Rows copied out of a texture have to be aligned
+    let bytes_per_row = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Recording Readback Buffer"),
+        size: (bytes_per_row * height) as _,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
 
-        render_pass.set_pipeline(&render_state.rectangle_render_pipeline);
-        render_pass.set_bind_group(0, &render_state.camera_bind_group, &[]);
-        render_pass.set_bind_group(1, &render_state.rectangle_bind_group, &[]);
-        render_pass.draw(0..4, 0..self.rectangles.len() as _);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Recording Encoder"),
+    });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Recording Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &texture_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_state.draw(&mut render_pass, callback);
     }
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = readback_buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for row in slice.get_mapped_range().chunks(bytes_per_row as usize) {
+        pixels.extend_from_slice(&row[..(width * 4) as usize]);
+    }
+    readback_buffer.unmap();
+    if matches!(
+        render_state.target_format,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+    ) {
+        for pixel in pixels.chunks_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    let file = std::fs::File::create(path).map_err(|error| error.to_string())?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|error| error.to_string())
 }
@@ -0,0 +1,580 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use cgmath::{Vector2, Vector3};
+
+use crate::physics::{
+    Bond, CustomElement, Element, Ellipse, Particle, Rectangle, Scene, WallMaterial,
+};
+
+/// Bump this whenever the format changes and teach the parsing how to fill in
+/// the fields that older versions don't have
+///
+/// - 1: position, velocity and element of the particles, bonds and rectangles
+/// - 2: adds the charge and whether the particle is frozen
+/// - 3: adds whether the rectangle is an absorber
+/// - 4: adds the material of the rectangle
+/// - 5: adds the collision layer and mask of the particle
+/// - 6: adds the lifetime of the particle, `none` for particles that don't decay
+/// - 7: adds custom elements, whose names are escaped by `escape_name`, and the radius
+///   override and custom element of the particle, along with ellipse lines for the
+///   particles that have an elliptical shape
+pub const SCENE_VERSION: u32 = 7;
+
+#[derive(Debug, PartialEq)]
+pub enum SceneFileError {
+    MissingVersion,
+    /// The scene was saved by a newer version of the simulation
+    UnsupportedVersion(u32),
+    InvalidLine {
+        line: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingVersion => write!(f, "the scene doesn't start with a version line"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "the scene is version {version} but only versions up to {SCENE_VERSION} are supported"
+            ),
+            Self::InvalidLine { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+/// Writes the scene as one object per line, along with all of `custom_elements` so the
+/// particles made of them can be loaded elsewhere
+pub fn save_scene(scene: &Scene, custom_elements: &[CustomElement]) -> String {
+    let mut text = format!("version {SCENE_VERSION}\n");
+    for custom_element in custom_elements {
+        text += &format!(
+            "element {} {} {} {} {} {} {}\n",
+            custom_element.base.name(),
+            custom_element.mass,
+            custom_element.electrons_to_share,
+            custom_element.color.x,
+            custom_element.color.y,
+            custom_element.color.z,
+            escape_name(&custom_element.name),
+        );
+    }
+    let optional = |value: Option<String>| value.unwrap_or("none".to_string());
+    for particle in &scene.particles {
+        text += &format!(
+            "particle {} {} {} {} {} {} {} {} {} {} {} {}\n",
+            particle.element.name(),
+            particle.position.x,
+            particle.position.y,
+            particle.velocity.x,
+            particle.velocity.y,
+            particle.charge,
+            particle.frozen,
+            particle.layer,
+            particle.collision_mask,
+            optional(particle.lifetime.map(|lifetime| lifetime.to_string())),
+            optional(particle.radius_override.map(|radius| radius.to_string())),
+            optional(particle.custom_element.map(|index| index.to_string())),
+        );
+    }
+    for (i, particle) in scene.particles.iter().enumerate() {
+        if let Some(ellipse) = particle.ellipse {
+            text += &format!(
+                "ellipse {i} {} {} {}\n",
+                ellipse.semi_axes.x, ellipse.semi_axes.y, ellipse.orientation,
+            );
+        }
+    }
+    for (&(a, b), bond) in &scene.bonds {
+        text += &format!("bond {a} {b} {}\n", bond.rest_length);
+    }
+    for rectangle in &scene.rectangles {
+        text += &format!(
+            "rectangle {} {} {} {} {} {} {} {} {}\n",
+            rectangle.position.x,
+            rectangle.position.y,
+            rectangle.size.x,
+            rectangle.size.y,
+            rectangle.color.x,
+            rectangle.color.y,
+            rectangle.color.z,
+            rectangle.absorber,
+            rectangle.material.name(),
+        );
+    }
+    text
+}
+
+/// Reads a scene written by `save_scene` in this or any older version. The custom elements
+/// it was saved with are added to `custom_elements`, unless an identical one is already there.
+pub fn load_scene(
+    text: &str,
+    custom_elements: &mut Vec<CustomElement>,
+) -> Result<Scene, SceneFileError> {
+    // Everything after a # and blank lines are ignored so scenes can be edited by hand
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    let version = match lines.next() {
+        Some((line, text)) => match text.strip_prefix("version ") {
+            Some(version) => parse(line, version.trim())?,
+            None => return Err(SceneFileError::MissingVersion),
+        },
+        None => return Err(SceneFileError::MissingVersion),
+    };
+    if version == 0 || version > SCENE_VERSION {
+        return Err(SceneFileError::UnsupportedVersion(version));
+    }
+
+    let mut scene = Scene {
+        particles: vec![],
+        bonds: BTreeMap::new(),
+        rectangles: vec![],
+    };
+    let mut saved_elements = vec![];
+    for (line, text) in lines {
+        let mut fields = text.split_whitespace();
+        let kind = fields.next().unwrap_or_default();
+        let fields: Vec<&str> = fields.collect();
+        match kind {
+            "element" if version >= 7 => {
+                // An empty name leaves nothing to write after the color
+                if fields.len() != 6 && fields.len() != 7 {
+                    return Err(SceneFileError::InvalidLine {
+                        line,
+                        reason: format!("expected 6 or 7 values but found {}", fields.len()),
+                    });
+                }
+                saved_elements.push(CustomElement {
+                    name: match fields.get(6) {
+                        Some(field) => unescape_name(line, field)?,
+                        None => String::new(),
+                    },
+                    base: parse_element(line, fields[0])?,
+                    mass: parse(line, fields[1])?,
+                    electrons_to_share: parse(line, fields[2])?,
+                    color: Vector3 {
+                        x: parse(line, fields[3])?,
+                        y: parse(line, fields[4])?,
+                        z: parse(line, fields[5])?,
+                    },
+                });
+            }
+            "particle" => {
+                let particle = parse_particle(line, version, &fields)?;
+                if particle
+                    .custom_element
+                    .is_some_and(|index| index >= saved_elements.len())
+                {
+                    return Err(SceneFileError::InvalidLine {
+                        line,
+                        reason: "the particle's custom element isn't defined above it".into(),
+                    });
+                }
+                scene.particles.push(particle);
+            }
+            "ellipse" if version >= 7 => {
+                expect_fields(line, &fields, 4)?;
+                let i: usize = parse(line, fields[0])?;
+                let Some(particle) = scene.particles.get_mut(i) else {
+                    return Err(SceneFileError::InvalidLine {
+                        line,
+                        reason: format!("there is no particle {i} to give an ellipse"),
+                    });
+                };
+                particle.ellipse = Some(Ellipse {
+                    semi_axes: Vector2 {
+                        x: parse(line, fields[1])?,
+                        y: parse(line, fields[2])?,
+                    },
+                    orientation: parse(line, fields[3])?,
+                });
+            }
+            "bond" => {
+                expect_fields(line, &fields, 3)?;
+                let a: usize = parse(line, fields[0])?;
+                let b: usize = parse(line, fields[1])?;
+                if a == b || a.max(b) >= scene.particles.len() {
+                    return Err(SceneFileError::InvalidLine {
+                        line,
+                        reason: format!("bond between {a} and {b} doesn't connect two particles"),
+                    });
+                }
+                scene.bonds.insert(
+                    (a.min(b), a.max(b)),
+                    Bond {
+                        rest_length: parse(line, fields[2])?,
+                    },
+                );
+            }
+            "rectangle" => {
+                expect_fields(
+                    line,
+                    &fields,
+                    match version {
+                        1 | 2 => 7,
+                        3 => 8,
+                        _ => 9,
+                    },
+                )?;
+                scene.rectangles.push(Rectangle {
+                    position: Vector2 {
+                        x: parse(line, fields[0])?,
+                        y: parse(line, fields[1])?,
+                    },
+                    size: Vector2 {
+                        x: parse(line, fields[2])?,
+                        y: parse(line, fields[3])?,
+                    },
+                    color: Vector3 {
+                        x: parse(line, fields[4])?,
+                        y: parse(line, fields[5])?,
+                        z: parse(line, fields[6])?,
+                    },
+                    // Older versions don't have absorbers
+                    absorber: version >= 3 && parse(line, fields[7])?,
+                    // Older versions only have elastic walls
+                    material: if version >= 4 {
+                        parse_material(line, fields[8])?
+                    } else {
+                        WallMaterial::Elastic
+                    },
+                    selected: false,
+                });
+            }
+            _ => {
+                return Err(SceneFileError::InvalidLine {
+                    line,
+                    reason: format!("unknown object '{kind}'"),
+                })
+            }
+        }
+    }
+
+    // Point the particles at where their elements ended up in `custom_elements`
+    let indices: Vec<usize> = saved_elements
+        .into_iter()
+        .map(|saved| {
+            custom_elements
+                .iter()
+                .position(|custom_element| *custom_element == saved)
+                .unwrap_or_else(|| {
+                    custom_elements.push(saved);
+                    custom_elements.len() - 1
+                })
+        })
+        .collect();
+    for particle in &mut scene.particles {
+        if let Some(ref mut index) = particle.custom_element {
+            *index = indices[*index];
+        }
+    }
+    Ok(scene)
+}
+
+fn parse_particle(line: usize, version: u32, fields: &[&str]) -> Result<Particle, SceneFileError> {
+    expect_fields(
+        line,
+        fields,
+        match version {
+            1 => 5,
+            2..=4 => 7,
+            5 => 9,
+            6 => 10,
+            _ => 12,
+        },
+    )?;
+    let element = parse_element(line, fields[0])?;
+    let mut particle = Particle::new(
+        Vector2 {
+            x: parse(line, fields[1])?,
+            y: parse(line, fields[2])?,
+        },
+        Vector2 {
+            x: parse(line, fields[3])?,
+            y: parse(line, fields[4])?,
+        },
+        element,
+    );
+    // Older versions keep the defaults from `Particle::new` for the fields they lack
+    if version >= 2 {
+        particle.charge = parse(line, fields[5])?;
+        particle.frozen = parse(line, fields[6])?;
+    }
+    if version >= 5 {
+        particle.layer = parse(line, fields[7])?;
+        particle.collision_mask = parse(line, fields[8])?;
+    }
+    if version >= 6 {
+        particle.lifetime = parse_optional(line, fields[9])?;
+    }
+    if version >= 7 {
+        particle.radius_override = parse_optional(line, fields[10])?;
+        particle.custom_element = parse_optional(line, fields[11])?;
+    }
+    Ok(particle)
+}
+
+fn parse_element(line: usize, field: &str) -> Result<Element, SceneFileError> {
+    Element::ALL
+        .into_iter()
+        .find(|element| element.name() == field)
+        .ok_or_else(|| SceneFileError::InvalidLine {
+            line,
+            reason: format!("unknown element '{field}'"),
+        })
+}
+
+/// Writes `%`, `#` and whitespace as `%` and the hex of their bytes, so the name stays one
+/// field and isn't cut off as a comment
+fn escape_name(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        if c == '%' || c == '#' || c.is_whitespace() {
+            for byte in c.to_string().bytes() {
+                escaped += &format!("%{byte:02X}");
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn unescape_name(line: usize, field: &str) -> Result<String, SceneFileError> {
+    let invalid = || SceneFileError::InvalidLine {
+        line,
+        reason: format!("invalid name '{field}'"),
+    };
+    let mut bytes = vec![];
+    let mut rest = field.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = after.get(..2).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn parse_material(line: usize, field: &str) -> Result<WallMaterial, SceneFileError> {
+    WallMaterial::ALL
+        .into_iter()
+        .find(|material| material.name() == field)
+        .ok_or_else(|| SceneFileError::InvalidLine {
+            line,
+            reason: format!("unknown material '{field}'"),
+        })
+}
+
+fn expect_fields(line: usize, fields: &[&str], count: usize) -> Result<(), SceneFileError> {
+    if fields.len() == count {
+        Ok(())
+    } else {
+        Err(SceneFileError::InvalidLine {
+            line,
+            reason: format!("expected {count} values but found {}", fields.len()),
+        })
+    }
+}
+
+fn parse<T: FromStr>(line: usize, field: &str) -> Result<T, SceneFileError> {
+    field.parse().map_err(|_| SceneFileError::InvalidLine {
+        line,
+        reason: format!("invalid value '{field}'"),
+    })
+}
+
+/// `none` for values that aren't set
+fn parse_optional<T: FromStr>(line: usize, field: &str) -> Result<Option<T>, SceneFileError> {
+    match field {
+        "none" => Ok(None),
+        _ => parse(line, field).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_1_scene_gets_defaults_for_newer_fields() {
+        let scene = load_scene(
+            "version 1
+            # two hydrogen atoms in a box
+            particle Hydrogen -1 0 0.5 0
+            particle Hydrogen 1 0 -0.5 0
+            bond 0 1 2
+            rectangle 0 -5 10 1 0.1 0.1 0.1
+            ",
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(scene.particles.len(), 2);
+        assert_eq!(scene.particles[0].position, Vector2::new(-1.0, 0.0));
+        assert_eq!(scene.particles[1].velocity, Vector2::new(-0.5, 0.0));
+        for particle in &scene.particles {
+            assert_eq!(particle.charge, 0.0);
+            assert!(!particle.frozen);
+            assert_eq!(particle.layer, 0);
+            assert_eq!(particle.collision_mask, u32::MAX);
+            assert_eq!(particle.lifetime, None);
+        }
+        assert_eq!(scene.bonds[&(0, 1)].rest_length, 2.0);
+        assert_eq!(scene.rectangles[0].size, Vector2::new(10.0, 1.0));
+        assert_eq!(scene.rectangles[0].material, WallMaterial::Elastic);
+    }
+
+    #[test]
+    fn hand_written_scene_with_comments() {
+        let scene = load_scene(
+            "# A startup scene
+            version 3
+
+            particle Oxygen 0 0 0 0 0 false    # the center
+            particle Hydrogen -2 0 0 0 0.5 true # pinned to the left
+            bond 1 0 2.5
+            rectangle 0 -10 40 1 0.2 0.2 0.2 false # floor
+            rectangle 15 0 2 2 0.8 0.1 0.1 true    # drain
+            ",
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(scene.particles.len(), 2);
+        assert_eq!(scene.particles[0].element, Element::Oxygen);
+        assert_eq!(scene.particles[1].position, Vector2::new(-2.0, 0.0));
+        assert_eq!(scene.particles[1].charge, 0.5);
+        assert!(scene.particles[1].frozen);
+        assert_eq!(scene.bonds[&(0, 1)].rest_length, 2.5);
+        assert_eq!(scene.rectangles.len(), 2);
+        assert_eq!(scene.rectangles[0].size, Vector2::new(40.0, 1.0));
+        assert!(!scene.rectangles[0].absorber);
+        assert!(scene.rectangles[1].absorber);
+        for rectangle in &scene.rectangles {
+            assert_eq!(rectangle.material, WallMaterial::Elastic);
+        }
+    }
+
+    #[test]
+    fn version_4_scene_has_wall_materials() {
+        let scene = load_scene(
+            "version 4
+            rectangle 0 -10 40 1 0.2 0.2 0.2 false Sticky # floor
+            rectangle 15 0 2 2 0.8 0.1 0.1 true Damping   # drain
+            ",
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(scene.rectangles[0].material, WallMaterial::Sticky);
+        assert!(!scene.rectangles[0].absorber);
+        assert_eq!(scene.rectangles[1].material, WallMaterial::Damping);
+        assert!(scene.rectangles[1].absorber);
+    }
+
+    #[test]
+    fn saved_scene_loads_unchanged() {
+        let mut scene = crate::presets::molecules();
+        scene.particles[0].charge = -1.5;
+        scene.particles[1].frozen = true;
+        scene.particles[2].layer = 3;
+        scene.particles[2].collision_mask = 0b1010;
+        scene.particles[3].lifetime = Some(2.5);
+        scene.particles[4].radius_override = Some(0.75);
+        scene.particles[5].ellipse = Some(Ellipse {
+            semi_axes: Vector2::new(2.0, 0.5),
+            orientation: 0.3,
+        });
+        let custom_elements = vec![
+            CustomElement::water(),
+            CustomElement {
+                name: "Heavy Water".into(),
+                mass: 20.0,
+                ..CustomElement::water()
+            },
+        ];
+        scene.particles[6].custom_element = Some(1);
+        scene.rectangles[0].absorber = true;
+        scene.rectangles[1].material = WallMaterial::Damping;
+        // Loading next to an existing registry reuses the identical element and appends the other
+        let mut loaded_elements = vec![
+            CustomElement {
+                name: "Ozone".into(),
+                ..CustomElement::water()
+            },
+            CustomElement::water(),
+        ];
+        let loaded =
+            load_scene(&save_scene(&scene, &custom_elements), &mut loaded_elements).unwrap();
+        assert_eq!(loaded.particles.len(), scene.particles.len());
+        for (loaded, particle) in loaded.particles.iter().zip(&scene.particles) {
+            assert_eq!(loaded.element, particle.element);
+            assert_eq!(loaded.position, particle.position);
+            assert_eq!(loaded.velocity, particle.velocity);
+            assert_eq!(loaded.charge, particle.charge);
+            assert_eq!(loaded.frozen, particle.frozen);
+            assert_eq!(loaded.layer, particle.layer);
+            assert_eq!(loaded.collision_mask, particle.collision_mask);
+            assert_eq!(loaded.lifetime, particle.lifetime);
+            assert_eq!(loaded.radius_override, particle.radius_override);
+            assert_eq!(loaded.ellipse, particle.ellipse);
+            assert!(
+                loaded.custom_element(&loaded_elements)
+                    == particle.custom_element(&custom_elements)
+            );
+        }
+        assert_eq!(loaded_elements.len(), 3);
+        assert_eq!(
+            loaded.bonds.keys().collect::<Vec<_>>(),
+            scene.bonds.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.rectangles.len(), scene.rectangles.len());
+        assert!(loaded.rectangles[0].absorber);
+        assert_eq!(loaded.rectangles[1].material, WallMaterial::Damping);
+    }
+
+    #[test]
+    fn custom_element_names_load_unchanged() {
+        let custom_elements: Vec<CustomElement> =
+            ["", "Heavy  #2 Water", "100%\tpure", "Ünobtainium"]
+                .into_iter()
+                .map(|name| CustomElement {
+                    name: name.into(),
+                    ..CustomElement::water()
+                })
+                .collect();
+        let text = save_scene(&crate::presets::molecules(), &custom_elements);
+        let mut loaded_elements = vec![];
+        load_scene(&text, &mut loaded_elements).unwrap();
+        assert!(loaded_elements == custom_elements);
+        // Loading again finds every element already there
+        load_scene(&text, &mut loaded_elements).unwrap();
+        assert_eq!(loaded_elements.len(), custom_elements.len());
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        assert_eq!(
+            load_scene(&format!("version {}\n", SCENE_VERSION + 1), &mut vec![]).err(),
+            Some(SceneFileError::UnsupportedVersion(SCENE_VERSION + 1))
+        );
+        assert_eq!(
+            load_scene("particle Hydrogen 0 0 0 0", &mut vec![]).err(),
+            Some(SceneFileError::MissingVersion)
+        );
+        assert!(matches!(
+            load_scene("version 3\nparticle Hydrogen 0 0 0 0", &mut vec![]),
+            Err(SceneFileError::InvalidLine { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,355 @@
+use std::{collections::BTreeMap, fmt};
+
+use cgmath::{Vector2, Vector3};
+use toml_edit::{Document, Table, Value};
+
+use crate::physics::{Bond, Element, Particle, Rectangle, Scene, WallMaterial};
+
+#[derive(Debug, PartialEq)]
+pub enum SceneTomlError {
+    /// The text isn't valid TOML
+    Syntax(String),
+    InvalidValue {
+        /// Where the value is, like `particles[2].position`
+        key: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for SceneTomlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Syntax(error) => write!(f, "{error}"),
+            Self::InvalidValue { key, reason } => write!(f, "{key}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneTomlError {}
+
+/// Reads a scene written by hand, which unlike the files of `scene_file` can leave out
+/// everything but the keys marked as required:
+///
+/// ```toml
+/// [[particles]]
+/// element = "Hydrogen" # required
+/// position = [-1, 0]   # required
+/// velocity = [0.5, 0]
+/// charge = 0
+/// frozen = false
+///
+/// [[bonds]]
+/// particles = [0, 1]   # required, in the order the particles are listed
+/// rest_length = 2      # required
+///
+/// [[rectangles]]
+/// position = [0, -5]   # required
+/// size = [10, 1]       # required
+/// color = [0.1, 0.1, 0.1]
+/// absorber = false
+/// material = "Elastic"
+/// ```
+pub fn load_scene_toml(text: &str) -> Result<Scene, SceneTomlError> {
+    let document: Document = text
+        .parse()
+        .map_err(|error: toml_edit::TomlError| SceneTomlError::Syntax(error.to_string()))?;
+    let root = Fields::new(document.as_table(), "scene".into(), &KEYS)?;
+
+    let mut scene = Scene {
+        particles: vec![],
+        bonds: BTreeMap::new(),
+        rectangles: vec![],
+    };
+    for fields in root.tables("particles", &PARTICLE_KEYS)? {
+        let mut particle = Particle::new(
+            fields.required("position", vector)?,
+            fields
+                .optional("velocity", vector)?
+                .unwrap_or(Vector2::new(0.0, 0.0)),
+            fields.required("element", element)?,
+        );
+        if let Some(charge) = fields.optional("charge", number)? {
+            particle.charge = charge;
+        }
+        if let Some(frozen) = fields.optional("frozen", Value::as_bool)? {
+            particle.frozen = frozen;
+        }
+        scene.particles.push(particle);
+    }
+    for fields in root.tables("bonds", &BOND_KEYS)? {
+        let [a, b] = fields.required("particles", index_pair)?;
+        if a == b || a.max(b) >= scene.particles.len() {
+            return Err(fields.invalid(
+                "particles",
+                format!("bond between {a} and {b} doesn't connect two particles"),
+            ));
+        }
+        scene.bonds.insert(
+            (a.min(b), a.max(b)),
+            Bond {
+                rest_length: fields.required("rest_length", number)?,
+            },
+        );
+    }
+    for fields in root.tables("rectangles", &RECTANGLE_KEYS)? {
+        let material = fields
+            .optional("material", wall_material)?
+            .unwrap_or(WallMaterial::Elastic);
+        scene.rectangles.push(Rectangle {
+            position: fields.required("position", vector)?,
+            size: fields.required("size", vector)?,
+            color: fields.optional("color", color)?.unwrap_or(material.color()),
+            absorber: fields
+                .optional("absorber", Value::as_bool)?
+                .unwrap_or(false),
+            material,
+            selected: false,
+        });
+    }
+    Ok(scene)
+}
+
+const KEYS: [(&str, &str); 3] = [
+    ("particles", "[[particles]] tables"),
+    ("bonds", "[[bonds]] tables"),
+    ("rectangles", "[[rectangles]] tables"),
+];
+const PARTICLE_KEYS: [(&str, &str); 5] = [
+    ("element", "an element name"),
+    ("position", "[x, y]"),
+    ("velocity", "[x, y]"),
+    ("charge", "a number"),
+    ("frozen", "true or false"),
+];
+const BOND_KEYS: [(&str, &str); 2] = [
+    ("particles", "two particle indices"),
+    ("rest_length", "a number"),
+];
+const RECTANGLE_KEYS: [(&str, &str); 5] = [
+    ("position", "[x, y]"),
+    ("size", "[width, height]"),
+    ("color", "[red, green, blue]"),
+    ("absorber", "true or false"),
+    ("material", "a wall material name"),
+];
+
+/// A table along with where it is in the file and what its keys are expected to hold,
+/// which the errors are written from
+struct Fields<'a> {
+    table: &'a Table,
+    path: String,
+    keys: &'static [(&'static str, &'static str)],
+}
+
+impl<'a> Fields<'a> {
+    /// Unknown keys are errors so that a misspelled key isn't silently ignored
+    fn new(
+        table: &'a Table,
+        path: String,
+        keys: &'static [(&'static str, &'static str)],
+    ) -> Result<Fields<'a>, SceneTomlError> {
+        let fields = Fields { table, path, keys };
+        match table
+            .iter()
+            .find(|(key, _)| !keys.iter().any(|&(known, _)| known == *key))
+        {
+            Some((key, _)) => Err(fields.invalid(key, "unknown key".into())),
+            None => Ok(fields),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}.{key}", self.path)
+    }
+
+    fn invalid(&self, key: &str, reason: String) -> SceneTomlError {
+        SceneTomlError::InvalidValue {
+            key: self.key(key),
+            reason,
+        }
+    }
+
+    fn expected(&self, key: &str) -> &'static str {
+        self.keys
+            .iter()
+            .find(|&&(known, _)| known == key)
+            .map_or("", |&(_, expected)| expected)
+    }
+
+    fn optional<T>(
+        &self,
+        key: &str,
+        parse: impl Fn(&Value) -> Option<T>,
+    ) -> Result<Option<T>, SceneTomlError> {
+        match self.table.get(key) {
+            Some(item) => match item.as_value().and_then(parse) {
+                Some(value) => Ok(Some(value)),
+                None => Err(self.invalid(key, format!("expected {}", self.expected(key)))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn required<T>(
+        &self,
+        key: &str,
+        parse: impl Fn(&Value) -> Option<T>,
+    ) -> Result<T, SceneTomlError> {
+        self.optional(key, parse)?
+            .ok_or_else(|| self.invalid(key, format!("missing, expected {}", self.expected(key))))
+    }
+
+    /// The `[[key]]` tables, every one checked against `keys`
+    fn tables(
+        &self,
+        key: &str,
+        keys: &'static [(&'static str, &'static str)],
+    ) -> Result<Vec<Fields<'a>>, SceneTomlError> {
+        let Some(item) = self.table.get(key) else {
+            return Ok(vec![]);
+        };
+        let tables = item
+            .as_array_of_tables()
+            .ok_or_else(|| self.invalid(key, format!("expected {}", self.expected(key))))?;
+        tables
+            .iter()
+            .enumerate()
+            .map(|(i, table)| Fields::new(table, format!("{key}[{i}]"), keys))
+            .collect()
+    }
+}
+
+/// Whole numbers are accepted too, so `1` doesn't have to be written as `1.0`
+fn number(value: &Value) -> Option<f32> {
+    value
+        .as_float()
+        .or_else(|| value.as_integer().map(|integer| integer as f64))
+        .map(|number| number as f32)
+}
+
+fn numbers<const N: usize>(value: &Value) -> Option<[f32; N]> {
+    let array = value.as_array()?;
+    if array.len() != N {
+        return None;
+    }
+    let mut numbers = [0.0; N];
+    for (slot, value) in numbers.iter_mut().zip(array.iter()) {
+        *slot = number(value)?;
+    }
+    Some(numbers)
+}
+
+fn vector(value: &Value) -> Option<Vector2<f32>> {
+    numbers(value).map(|[x, y]| Vector2 { x, y })
+}
+
+fn color(value: &Value) -> Option<Vector3<f32>> {
+    numbers(value).map(|[x, y, z]| Vector3 { x, y, z })
+}
+
+fn index_pair(value: &Value) -> Option<[usize; 2]> {
+    let array = value.as_array()?;
+    let mut indices = array
+        .iter()
+        .map(|value| usize::try_from(value.as_integer()?).ok());
+    match (indices.next(), indices.next(), indices.next()) {
+        (Some(Some(a)), Some(Some(b)), None) => Some([a, b]),
+        _ => None,
+    }
+}
+
+fn element(value: &Value) -> Option<Element> {
+    let name = value.as_str()?;
+    Element::ALL
+        .into_iter()
+        .find(|element| element.name() == name)
+}
+
+fn wall_material(value: &Value) -> Option<WallMaterial> {
+    let name = value.as_str()?;
+    WallMaterial::ALL
+        .into_iter()
+        .find(|material| material.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hand_written_toml_scene() {
+        let scene = load_scene_toml(
+            r#"
+            # Water about to form
+            [[particles]]
+            element = "Oxygen"
+            position = [0, 0]
+
+            [[particles]]
+            element = "Hydrogen"
+            position = [-2.5, 0.5]
+            velocity = [1, 0]
+            charge = 0.5
+            frozen = true
+
+            [[bonds]]
+            particles = [1, 0]
+            rest_length = 2.5
+
+            [[rectangles]] # floor
+            position = [0, -10]
+            size = [40, 1]
+            material = "Sticky"
+
+            [[rectangles]] # drain
+            position = [15, 0]
+            size = [2, 2]
+            color = [0.8, 0.1, 0.1]
+            absorber = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scene.particles.len(), 2);
+        assert_eq!(scene.particles[0].element, Element::Oxygen);
+        assert_eq!(scene.particles[0].velocity, Vector2::new(0.0, 0.0));
+        assert!(!scene.particles[0].frozen);
+        assert_eq!(scene.particles[1].position, Vector2::new(-2.5, 0.5));
+        assert_eq!(scene.particles[1].velocity, Vector2::new(1.0, 0.0));
+        assert_eq!(scene.particles[1].charge, 0.5);
+        assert!(scene.particles[1].frozen);
+        assert_eq!(scene.bonds[&(0, 1)].rest_length, 2.5);
+        assert_eq!(scene.rectangles.len(), 2);
+        assert_eq!(scene.rectangles[0].size, Vector2::new(40.0, 1.0));
+        assert_eq!(scene.rectangles[0].material, WallMaterial::Sticky);
+        assert_eq!(scene.rectangles[0].color, WallMaterial::Sticky.color());
+        assert!(!scene.rectangles[0].absorber);
+        assert_eq!(scene.rectangles[1].color, Vector3::new(0.8, 0.1, 0.1));
+        assert_eq!(scene.rectangles[1].material, WallMaterial::Elastic);
+        assert!(scene.rectangles[1].absorber);
+    }
+
+    #[test]
+    fn invalid_toml_scenes_say_where_the_problem_is() {
+        let error = |text: &str| load_scene_toml(text).err().unwrap().to_string();
+        assert_eq!(
+            error("[[particles]]\nelement = \"Helium\"\nposition = [0, 0]"),
+            "particles[0].element: expected an element name"
+        );
+        assert_eq!(
+            error("[[particles]]\nelement = \"Hydrogen\""),
+            "particles[0].position: missing, expected [x, y]"
+        );
+        assert_eq!(
+            error("[[rectangles]]\nposition = [0, 0]\nsize = [1, 1]\nsolid = true"),
+            "rectangles[0].solid: unknown key"
+        );
+        assert_eq!(
+            error("[[bonds]]\nparticles = [0, 1]\nrest_length = 1"),
+            "bonds[0].particles: bond between 0 and 1 doesn't connect two particles"
+        );
+        assert!(matches!(
+            load_scene_toml("[[particles]\n"),
+            Err(SceneTomlError::Syntax(_))
+        ));
+    }
+}
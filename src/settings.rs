@@ -0,0 +1,117 @@
+use std::{collections::BTreeMap, fmt::Display, ops::RangeInclusive, path::PathBuf, str::FromStr};
+
+/// Preferences saved between runs as one `key value` line each. Keys that aren't read
+/// and values that don't parse are ignored, so settings from other versions still load.
+#[derive(Default)]
+pub struct Settings {
+    values: BTreeMap<String, String>,
+}
+
+impl Settings {
+    /// `file_name` in the per-user config directory of the app, so the settings are found
+    /// wherever the app is started from: `$XDG_CONFIG_HOME` or `~/.config` on Linux,
+    /// `~/Library/Application Support` on macOS and `%APPDATA%` on Windows
+    pub fn path(app_name: &str, file_name: &str) -> Option<PathBuf> {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        let config_dir = if cfg!(target_os = "windows") {
+            PathBuf::from(var("APPDATA")?)
+        } else if cfg!(target_os = "macos") {
+            PathBuf::from(var("HOME")?).join("Library/Application Support")
+        } else {
+            var("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| Some(PathBuf::from(var("HOME")?).join(".config")))?
+        };
+        Some(config_dir.join(app_name).join(file_name))
+    }
+
+    pub fn parse(text: &str) -> Settings {
+        Settings {
+            values: text
+                .lines()
+                .filter_map(|line| line.trim().split_once(' '))
+                .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        self.values
+            .iter()
+            .map(|(key, value)| format!("{key} {value}\n"))
+            .collect()
+    }
+
+    pub fn write(&mut self, key: &str, value: impl Display) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    /// Overwrites `value` with the saved one, leaving it alone if there is none
+    pub fn read<T: FromStr>(&self, key: &str, value: &mut T) {
+        if let Some(saved) = self.values.get(key).and_then(|saved| saved.parse().ok()) {
+            *value = saved;
+        }
+    }
+
+    /// Like `read` but clamps the saved value into `range`, ignoring it if it's NaN, so a
+    /// hand edited file can't set anything the UI wouldn't allow
+    pub fn read_clamped<T: FromStr + PartialOrd + Copy>(
+        &self,
+        key: &str,
+        value: &mut T,
+        range: RangeInclusive<T>,
+    ) {
+        let mut saved = *value;
+        self.read(key, &mut saved);
+        if saved < *range.start() {
+            *value = *range.start();
+        } else if saved > *range.end() {
+            *value = *range.end();
+        } else if saved >= *range.start() && saved <= *range.end() {
+            *value = saved;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_settings_load_unchanged() {
+        let mut settings = Settings::default();
+        settings.write("zoom", 0.125);
+        settings.write("show_hud", true);
+        settings.write("time_scale", 4);
+        let settings =
+            Settings::parse(&(settings.to_text() + "unknown_key 1\nzoom_sensitivity x\n"));
+
+        let mut zoom = 1.0;
+        let mut show_hud = false;
+        let mut time_scale = 1;
+        let mut zoom_sensitivity = 1.0;
+        settings.read("zoom", &mut zoom);
+        settings.read("show_hud", &mut show_hud);
+        settings.read("time_scale", &mut time_scale);
+        settings.read("zoom_sensitivity", &mut zoom_sensitivity);
+        assert_eq!(zoom, 0.125);
+        assert!(show_hud);
+        assert_eq!(time_scale, 4);
+        assert_eq!(zoom_sensitivity, 1.0);
+    }
+
+    #[test]
+    fn clamped_settings_stay_in_range() {
+        let settings = Settings::parse("low -3\nhigh 500\nnan NaN\nfine 0.5\n");
+        let read = |key| {
+            let mut value = 0.25;
+            settings.read_clamped(key, &mut value, 0.1..=2.0);
+            value
+        };
+        assert_eq!(read("low"), 0.1);
+        assert_eq!(read("high"), 2.0);
+        assert_eq!(read("nan"), 0.25);
+        assert_eq!(read("fine"), 0.5);
+        assert_eq!(read("missing"), 0.25);
+    }
+}
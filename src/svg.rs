@@ -0,0 +1,112 @@
+use std::fmt::Write;
+
+use cgmath::{Vector2, Vector3};
+
+use crate::physics::{CustomElement, Scene};
+
+/// Draws the scene as an SVG image of `width` by `height` pixels, seen by a camera at
+/// `camera_position` with the same zoom as the renderer, where the height of the image
+/// spans `2 / zoom` world units
+pub fn to_svg(
+    scene: &Scene,
+    custom_elements: &[CustomElement],
+    camera_position: Vector2<f32>,
+    zoom: f32,
+    width: u32,
+    height: u32,
+) -> String {
+    let scale = zoom * height as f32 * 0.5;
+    let to_image = |position: Vector2<f32>| Vector2 {
+        x: width as f32 * 0.5 + (position.x - camera_position.x) * scale,
+        y: height as f32 * 0.5 - (position.y - camera_position.y) * scale,
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\">\n\
+         <rect width=\"{width}\" height=\"{height}\" fill=\"black\"/>\n"
+    );
+    for rectangle in &scene.rectangles {
+        // The top left corner in the image is the corner with the lowest x and highest y
+        let corner = to_image(Vector2 {
+            x: rectangle.position.x - rectangle.size.x * 0.5,
+            y: rectangle.position.y + rectangle.size.y * 0.5,
+        });
+        writeln!(
+            svg,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            corner.x,
+            corner.y,
+            rectangle.size.x * scale,
+            rectangle.size.y * scale,
+            hex_color(rectangle.color),
+        )
+        .unwrap();
+    }
+    for particle in &scene.particles {
+        let center = to_image(particle.position);
+        let color = hex_color(particle.color(custom_elements));
+        match particle.ellipse {
+            Some(ellipse) => writeln!(
+                svg,
+                "<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" fill=\"{color}\" \
+                 transform=\"rotate({} {} {})\"/>",
+                center.x,
+                center.y,
+                ellipse.semi_axes.x * scale,
+                ellipse.semi_axes.y * scale,
+                // The image y axis points down so the rotation flips
+                -ellipse.orientation.to_degrees(),
+                center.x,
+                center.y,
+            ),
+            None => writeln!(
+                svg,
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{color}\"/>",
+                center.x,
+                center.y,
+                particle.radius(custom_elements) * scale,
+            ),
+        }
+        .unwrap();
+    }
+    for &(a, b) in scene.bonds.keys() {
+        let start = to_image(scene.particles[a].position);
+        let end = to_image(scene.particles[b].position);
+        writeln!(
+            svg,
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"gray\" stroke-width=\"2\"/>",
+            start.x, start.y, end.x, end.y,
+        )
+        .unwrap();
+    }
+    svg += "</svg>\n";
+    svg
+}
+
+fn hex_color(color: Vector3<f32>) -> String {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color.x),
+        channel(color.y),
+        channel(color.z)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_particle_becomes_a_circle() {
+        let scene = crate::presets::molecules();
+        let svg = to_svg(&scene, &[], Vector2::new(0.0, 0.0), 0.05, 800, 600);
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<circle").count(), scene.particles.len());
+        assert_eq!(svg.matches("<line").count(), scene.bonds.len());
+        // The background and the walls
+        assert_eq!(svg.matches("<rect").count(), 1 + scene.rectangles.len());
+        assert!(svg.contains("fill=\"#ff0000\""));
+    }
+}